@@ -11,8 +11,8 @@
 	unused_qualifications,
 )]
 
-extern crate hdd;
-use hdd::drivedb::{self, Entry};
+extern crate drivedb;
+use drivedb::{Entry, Loader};
 
 #[macro_use]
 extern crate clap;
@@ -43,10 +43,8 @@ fn elapsed(i: Instant) -> f32 {
 	(elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32/1e9) * 1e3
 }
 
-fn find<'a>(db: &'a Vec<Entry>, model: &str, firmware: &str) -> Option<&'a Entry> {
-	for entry in db.iter() {
-		if entry.model.starts_with("USB:") { continue }
-
+fn find<'a>(db: &[&'a Entry], model: &str, firmware: &str) -> Option<&'a Entry> {
+	for &entry in db.iter() {
 		// model and firmware are expected to be ascii strings, no need to try matching unicode characters
 
 		// > [modelregexp] should never be "".
@@ -118,9 +116,29 @@ fn main() {
 	let firmware = args.value_of("firmware").unwrap();
 
 	let now = Instant::now();
-	let drivedb = drivedb::load(drivedb);
+	let mut loader = Loader::new();
+	loader.load(drivedb).unwrap();
 	println!("loaded drivedb in {:.4}ms", elapsed(now));
-	let drivedb = drivedb.unwrap();
+
+	// this is how the crate itself matches drives: RegexSets are compiled once, when the database is built
+	let now = Instant::now();
+	let db = loader.db().unwrap();
+	println!("compiled DriveDB in {:.4}ms", elapsed(now));
+
+	let now = Instant::now();
+	let meta = db.render_meta(&model, &firmware, None, &vec![]);
+	println!("render_meta() in {:.4}ms", elapsed(now));
+	println!("{:?}", meta.family);
+
+	for _ in 1..10 {
+		let now = Instant::now();
+		let meta = db.render_meta(&model, &firmware, None, &vec![]);
+		println!("render_meta() in {:.4}ms", elapsed(now));
+		black_box(meta); // make sure `meta` is not eliminated
+	}
+
+	// the rest of the benchmarks compare other approaches against the very same entries (USB bridges and the default entry are excluded)
+	let drivedb: Vec<_> = db.entries().collect();
 
 	let now = Instant::now();
 	let e = find(&drivedb, &model, &firmware);
@@ -136,8 +154,7 @@ fn main() {
 
 	let now = Instant::now();
 	let compiled: Vec<_> = drivedb.iter()
-		.filter(|e| ! e.model.starts_with("USB:"))
-		.map(|e| (
+		.map(|&e| (
 			e,
 			Regex::new(format!("(?-u)^{}$", e.model).as_str()).unwrap(),
 			if e.firmware.is_empty() { None } else {
@@ -161,8 +178,7 @@ fn main() {
 
 	let now = Instant::now();
 	let compiled: Vec<_> = drivedb.iter()
-		.filter(|e| ! e.model.starts_with("USB:"))
-		.map(|e| (
+		.map(|&e| (
 			e,
 			LazyRegex::new(format!("^{}$", e.model).as_str()).unwrap(),
 			if e.firmware.is_empty() { None } else {
@@ -186,11 +202,9 @@ fn main() {
 
 	let now = Instant::now();
 	let models = RegexSet::new(drivedb.iter()
-		.filter(|e| ! e.model.starts_with("USB:"))
 		.map(|e| format!("^{}$", e.model))
 	).unwrap();
 	let firmwares = RegexSet::new(drivedb.iter()
-		.filter(|e| ! e.model.starts_with("USB:"))
 		.map(|e| if e.firmware.is_empty() { "".to_string() } else {
 			format!("^{}$", e.firmware)
 		})
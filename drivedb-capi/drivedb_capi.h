@@ -0,0 +1,56 @@
+/*
+ * C API for the drivedb crate.
+ *
+ * All strings are NUL-terminated UTF-8. Strings returned by these functions are owned by the caller
+ * and must be released with drivedb_string_free().
+ */
+
+#ifndef DRIVEDB_CAPI_H
+#define DRIVEDB_CAPI_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Opaque handle to the loaded drive database. */
+typedef struct drivedb drivedb;
+
+/*
+ * Loads drive database from `file` (e.g. "/usr/share/smartmontools/drivedb.h"),
+ * plus entries from `additional` file, if it is not NULL.
+ *
+ * Returns NULL on failure; in that case, if `error` is not NULL, it is set to the error message.
+ */
+drivedb *drivedb_load(const char *file, const char *additional, char **error);
+
+/* Releases database returned by drivedb_load(). NULL is ignored. */
+void drivedb_free(drivedb *db);
+
+/*
+ * Matches drive against the database and returns JSON object with the drive family, warning,
+ * recommended device type, matched entry, and descriptions of attributes keyed by their ids.
+ *
+ * Only attributes explicitly mentioned in the database (or the default entry) are described there;
+ * descriptions that apply to all attributes at once are already merged into them.
+ * Use drivedb_render_attribute_json() for attributes that are not listed.
+ *
+ * `drivetype` is either "HDD", "SSD", or NULL if unknown.
+ *
+ * Returns NULL if arguments are invalid.
+ */
+char *drivedb_render_meta_json(const drivedb *db, const char *model, const char *firmware, const char *drivetype);
+
+/*
+ * Same as drivedb_render_meta_json(), but returns JSON object describing a single attribute `id`,
+ * or JSON null if there's no description for it.
+ */
+char *drivedb_render_attribute_json(const drivedb *db, const char *model, const char *firmware, const char *drivetype, unsigned char id);
+
+/* Releases strings returned by the functions above. NULL is ignored. */
+void drivedb_string_free(char *s);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* DRIVEDB_CAPI_H */
@@ -0,0 +1,244 @@
+/*!
+C API for the drivedb crate; see `drivedb_capi.h` for the function reference.
+
+Results are handed out as JSON strings so that bindings for other languages do not need to mirror any of the structures.
+*/
+
+#![warn(
+	missing_debug_implementations,
+	missing_docs,
+	trivial_casts,
+	trivial_numeric_casts,
+	unstable_features,
+	unused_import_braces,
+	unused_qualifications,
+)]
+// unsafe_code is unavoidable here, so let's not even warn about it
+
+extern crate drivedb;
+extern crate serde_json;
+
+use drivedb::{DriveDB, Loader, Type};
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+	if s.is_null() {
+		return None;
+	}
+	CStr::from_ptr(s).to_str().ok()
+}
+
+fn to_c_string(s: String) -> *mut c_char {
+	// JSON-encoded and error strings never contain NULs, but let's not panic across FFI boundary anyway
+	CString::new(s)
+		.map(CString::into_raw)
+		.unwrap_or(ptr::null_mut())
+}
+
+unsafe fn set_error(error: *mut *mut c_char, msg: String) {
+	if ! error.is_null() {
+		*error = to_c_string(msg);
+	}
+}
+
+fn load(file: &str, additional: Option<&str>) -> Result<DriveDB, drivedb::Error> {
+	let mut loader = Loader::new();
+	loader.load(file)?;
+	if let Some(additional) = additional {
+		loader.load_additional(additional)?;
+	}
+	loader.db()
+}
+
+/**
+Loads drive database from `file`, plus entries from `additional` file, if it is not NULL.
+
+## Safety
+
+`file` must be a valid NUL-terminated string; so must be `additional`, unless it is NULL. `error` must be either NULL or a valid pointer to write error message to.
+*/
+#[no_mangle]
+pub unsafe extern "C" fn drivedb_load(file: *const c_char, additional: *const c_char, error: *mut *mut c_char) -> *mut DriveDB {
+	let file = match to_str(file) {
+		Some(file) => file,
+		None => {
+			set_error(error, "invalid file name".to_string());
+			return ptr::null_mut();
+		},
+	};
+	if ! additional.is_null() && to_str(additional).is_none() {
+		set_error(error, "invalid additional file name".to_string());
+		return ptr::null_mut();
+	}
+
+	match load(file, to_str(additional)) {
+		Ok(db) => Box::into_raw(Box::new(db)),
+		Err(err) => {
+			set_error(error, err.to_string());
+			ptr::null_mut()
+		},
+	}
+}
+
+/**
+Releases database returned by `drivedb_load()`.
+
+## Safety
+
+`db` must be either NULL or a pointer returned by `drivedb_load()` that was not released yet.
+*/
+#[no_mangle]
+pub unsafe extern "C" fn drivedb_free(db: *mut DriveDB) {
+	if ! db.is_null() {
+		drop(Box::from_raw(db));
+	}
+}
+
+unsafe fn with_meta<F>(db: *const DriveDB, model: *const c_char, firmware: *const c_char, drivetype: *const c_char, f: F) -> *mut c_char
+where F: FnOnce(drivedb::DriveMeta) -> serde_json::Result<String> {
+	let db = match db.as_ref() {
+		Some(db) => db,
+		None => return ptr::null_mut(),
+	};
+	let (model, firmware) = match (to_str(model), to_str(firmware)) {
+		(Some(model), Some(firmware)) => (model, firmware),
+		_ => return ptr::null_mut(),
+	};
+	let drivetype = match to_str(drivetype) {
+		Some("HDD") => Some(Type::HDD),
+		Some("SSD") => Some(Type::SSD),
+		None if drivetype.is_null() => None,
+		_ => return ptr::null_mut(),
+	};
+
+	let meta = db.render_meta(model, firmware, drivetype, &vec![]);
+	f(meta)
+		.map(to_c_string)
+		.unwrap_or(ptr::null_mut())
+}
+
+/**
+Matches drive against the database, returning JSON-encoded `DriveMeta`.
+
+## Safety
+
+`db` must be either NULL or a pointer returned by `drivedb_load()`; strings must be either NULL or valid NUL-terminated strings.
+*/
+#[no_mangle]
+pub unsafe extern "C" fn drivedb_render_meta_json(db: *const DriveDB, model: *const c_char, firmware: *const c_char, drivetype: *const c_char) -> *mut c_char {
+	with_meta(db, model, firmware, drivetype, |meta| serde_json::to_string(&meta))
+}
+
+/**
+Matches drive against the database, returning JSON-encoded description of the attribute `id`.
+
+## Safety
+
+Same as for `drivedb_render_meta_json()`.
+*/
+#[no_mangle]
+pub unsafe extern "C" fn drivedb_render_attribute_json(db: *const DriveDB, model: *const c_char, firmware: *const c_char, drivetype: *const c_char, id: u8) -> *mut c_char {
+	with_meta(db, model, firmware, drivetype, |meta| serde_json::to_string(&meta.render_attribute(id)))
+}
+
+/**
+Releases strings returned by other functions.
+
+## Safety
+
+`s` must be either NULL or a string returned by other functions of this crate that was not released yet.
+*/
+#[no_mangle]
+pub unsafe extern "C" fn drivedb_string_free(s: *mut c_char) {
+	if ! s.is_null() {
+		drop(CString::from_raw(s));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::fs;
+	use std::process;
+
+	const DB: &str = r#"
+		{ "DEFAULT", "-", "", "", "-v 9,minutes" },
+		{ "Family", "Model", "", "", "-v 194,temp10x,Temperature_Celsius_x10" },
+	"#;
+
+	fn c(s: &str) -> CString {
+		CString::new(s).unwrap()
+	}
+
+	// takes ownership of the returned string
+	unsafe fn json(s: *mut c_char) -> Option<serde_json::Value> {
+		if s.is_null() {
+			return None;
+		}
+		let value = serde_json::from_slice(CStr::from_ptr(s).to_bytes()).unwrap();
+		drivedb_string_free(s);
+		Some(value)
+	}
+
+	#[test]
+	fn load_and_render() {
+		let file = env::temp_dir().join(format!("drivedb-capi-test-{}.h", process::id()));
+		fs::write(&file, DB).unwrap();
+		let file = c(file.to_str().unwrap());
+
+		unsafe {
+			let mut error = ptr::null_mut();
+			let db = drivedb_load(file.as_ptr(), ptr::null(), &mut error);
+			assert!(! db.is_null());
+			assert!(error.is_null());
+
+			let (model, firmware) = (c("Model"), c(""));
+
+			// unknown drive type
+			let meta = json(drivedb_render_meta_json(db, model.as_ptr(), firmware.as_ptr(), ptr::null())).unwrap();
+			assert_eq!(meta["family"], "Family");
+			assert_eq!(meta["attributes"]["9"]["name"], "Power_On_Minutes");
+			assert_eq!(meta["attributes"]["194"]["format"], "temp10x");
+
+			assert!(json(drivedb_render_meta_json(db, model.as_ptr(), firmware.as_ptr(), c("HDD").as_ptr())).is_some());
+			assert!(json(drivedb_render_meta_json(db, model.as_ptr(), firmware.as_ptr(), c("floppy").as_ptr())).is_none());
+			assert!(json(drivedb_render_meta_json(db, ptr::null(), firmware.as_ptr(), ptr::null())).is_none());
+			assert!(json(drivedb_render_meta_json(ptr::null(), model.as_ptr(), firmware.as_ptr(), ptr::null())).is_none());
+
+			let attr = json(drivedb_render_attribute_json(db, model.as_ptr(), firmware.as_ptr(), ptr::null(), 194)).unwrap();
+			assert_eq!(attr["name"], "Temperature_Celsius_x10");
+			let attr = json(drivedb_render_attribute_json(db, model.as_ptr(), firmware.as_ptr(), ptr::null(), 5)).unwrap();
+			assert!(attr.is_null());
+
+			drivedb_free(db);
+		}
+
+		fs::remove_file(file.to_str().unwrap()).unwrap();
+	}
+
+	#[test]
+	fn load_errors() {
+		unsafe {
+			let mut error = ptr::null_mut();
+			let db = drivedb_load(c("/nonexistent/drivedb.h").as_ptr(), ptr::null(), &mut error);
+			assert!(db.is_null());
+			assert!(CStr::from_ptr(error).to_str().unwrap().starts_with("/nonexistent/drivedb.h: IO error"));
+			drivedb_string_free(error);
+
+			// error is optional
+			assert!(drivedb_load(ptr::null(), ptr::null(), ptr::null_mut()).is_null());
+		}
+	}
+
+	#[test]
+	fn free_null() {
+		unsafe {
+			drivedb_free(ptr::null_mut());
+			drivedb_string_free(ptr::null_mut());
+		}
+	}
+}
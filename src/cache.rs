@@ -0,0 +1,214 @@
+/*
+Binary cache layout (all integers are little-endian):
+
+- magic: `MAGIC`,
+- format version: u32,
+- number of sources: u32, followed by that many of:
+	- kind: u8, followed by
+		- for files (0): path (string), size (u64), modification time (u64 seconds and u32 nanoseconds since UNIX epoch),
+		- for databases loaded from memory (1): FNV-1a hash of the contents (u64),
+- number of entries: u32, followed by that many of:
+	- family, model, firmware, warning, presets: string each,
+	- origin: file (string, empty if unknown), line and column (u32 each),
+
+where every string is its length in bytes (u32) followed by UTF-8 data.
+*/
+
+use super::loader::{self, Error};
+use super::parser::{Entry, Origin, Position};
+
+use std::fs;
+use std::io;
+use std::time::{Duration, UNIX_EPOCH};
+
+const MAGIC: &[u8; 8] = b"drivedb\0";
+// bump this every time the layout changes, or when `Entry` fields are added or change their meaning
+const VERSION: u32 = 3;
+
+/// Where entries were loaded from, with enough metadata to tell whether it was changed since
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+	File {
+		path: String,
+		len: u64,
+		mtime: Duration, // since UNIX epoch
+	},
+	// there's no file to check for changes, so the only way to tell is to compare contents
+	Memory {
+		hash: u64,
+	},
+}
+
+// FNV-1a: unlike `DefaultHasher`, it is guaranteed to yield the same results across Rust versions
+fn hash(data: &[u8]) -> u64 {
+	data.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3))
+}
+
+impl Source {
+	pub fn new(path: &str) -> io::Result<Self> {
+		let meta = fs::metadata(path)?;
+		Ok(Source::File {
+			path: path.to_string(),
+			len: meta.len(),
+			mtime: meta.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default(),
+		})
+	}
+	pub fn memory(data: &[u8]) -> Self {
+		Source::Memory { hash: hash(data) }
+	}
+	// `memory` is the current copy of the database that was loaded from memory, if any
+	fn is_fresh(&self, memory: Option<&[u8]>) -> bool {
+		match *self {
+			Source::File { ref path, .. } => Source::new(path)
+				.map(|current| current == *self)
+				.unwrap_or(false),
+			Source::Memory { hash: cached } => memory.map(hash) == Some(cached),
+		}
+	}
+}
+
+fn put_u32(out: &mut Vec<u8>, x: u32) { out.extend_from_slice(&x.to_le_bytes()) }
+fn put_u64(out: &mut Vec<u8>, x: u64) { out.extend_from_slice(&x.to_le_bytes()) }
+fn put_str(out: &mut Vec<u8>, s: &str) {
+	put_u32(out, s.len() as u32);
+	out.extend_from_slice(s.as_bytes());
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+		if self.data.len() < len { return None }
+		let (head, tail) = self.data.split_at(len);
+		self.data = tail;
+		Some(head)
+	}
+	fn u8(&mut self) -> Option<u8> {
+		self.take(1).map(|b| b[0])
+	}
+	fn u32(&mut self) -> Option<u32> {
+		let mut buf = [0; 4];
+		buf.copy_from_slice(self.take(4)?);
+		Some(u32::from_le_bytes(buf))
+	}
+	fn u64(&mut self) -> Option<u64> {
+		let mut buf = [0; 8];
+		buf.copy_from_slice(self.take(8)?);
+		Some(u64::from_le_bytes(buf))
+	}
+	fn string(&mut self) -> Option<String> {
+		let len = self.u32()? as usize;
+		String::from_utf8(self.take(len)?.to_vec()).ok()
+	}
+	fn source(&mut self) -> Option<Source> {
+		match self.u8()? {
+			0 => Some(Source::File {
+				path: self.string()?,
+				len: self.u64()?,
+				mtime: {
+					let secs = self.u64()?;
+					let nanos = self.u32()?;
+					Duration::new(secs, nanos)
+				},
+			}),
+			1 => Some(Source::Memory { hash: self.u64()? }),
+			_ => None,
+		}
+	}
+	fn entry(&mut self) -> Option<Entry> {
+		Some(Entry {
+			family: self.string()?,
+			model: self.string()?,
+			firmware: self.string()?,
+			warning: self.string()?,
+			presets: self.string()?,
+			origin: {
+				let file = self.string()?;
+				let line = self.u32()? as usize;
+				let column = self.u32()? as usize;
+				if file.is_empty() { None } else {
+					Some(Origin { file, position: Position { line, column } })
+				}
+			},
+		})
+	}
+}
+
+fn decode(data: &[u8]) -> Option<(Vec<Source>, Vec<Entry>)> {
+	let mut r = Reader { data };
+
+	if r.take(MAGIC.len())? != MAGIC || r.u32()? != VERSION {
+		return None;
+	}
+
+	let sources = (0 .. r.u32()?)
+		.map(|_| r.source())
+		.collect::<Option<Vec<_>>>()?;
+	let entries = (0 .. r.u32()?)
+		.map(|_| r.entry())
+		.collect::<Option<Vec<_>>>()?;
+
+	if ! r.data.is_empty() { return None }
+
+	Some((sources, entries))
+}
+
+pub fn save(file: &str, sources: &[Source], entries: &[&Entry]) -> Result<(), Error> {
+	let mut out = Vec::new();
+
+	out.extend_from_slice(MAGIC);
+	put_u32(&mut out, VERSION);
+
+	put_u32(&mut out, sources.len() as u32);
+	for s in sources {
+		match *s {
+			Source::File { ref path, len, mtime } => {
+				out.push(0);
+				put_str(&mut out, path);
+				put_u64(&mut out, len);
+				put_u64(&mut out, mtime.as_secs());
+				put_u32(&mut out, mtime.subsec_nanos());
+			},
+			Source::Memory { hash } => {
+				out.push(1);
+				put_u64(&mut out, hash);
+			},
+		}
+	}
+
+	put_u32(&mut out, entries.len() as u32);
+	for e in entries {
+		put_str(&mut out, &e.family);
+		put_str(&mut out, &e.model);
+		put_str(&mut out, &e.firmware);
+		put_str(&mut out, &e.warning);
+		put_str(&mut out, &e.presets);
+		match e.origin {
+			Some(ref origin) => {
+				put_str(&mut out, &origin.file);
+				put_u32(&mut out, origin.position.line as u32);
+				put_u32(&mut out, origin.position.column as u32);
+			},
+			None => {
+				put_str(&mut out, "");
+				put_u32(&mut out, 0);
+				put_u32(&mut out, 0);
+			},
+		}
+	}
+
+	loader::write(file, &out)
+}
+
+pub fn load(file: &str, memory: Option<&[u8]>) -> Result<(Vec<Source>, Vec<Entry>), Error> {
+	let (sources, entries) = decode(&loader::read(file)?)
+		.ok_or_else(|| Error::Cache(file.to_string()))?;
+
+	if ! sources.iter().all(|s| s.is_fresh(memory)) {
+		return Err(Error::StaleCache(file.to_string()));
+	}
+
+	Ok((sources, entries))
+}
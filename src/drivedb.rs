@@ -1,18 +1,24 @@
 use super::{presets, Attribute};
-use super::parser::Entry;
-use regex;
-use regex::bytes::{RegexSet, RegexSetBuilder};
-use std::collections::HashSet;
+use super::presets::RecommendedType;
+use super::warning::{self, Warning};
+use super::parser::{Entry, Origin};
+use super::usb::UsbEntry;
+use super::loader;
+use super::cache::{self, Source};
+use regex::bytes::{RegexBuilder, RegexSet, RegexSetBuilder};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::slice;
 use super::vendor_attribute::Type;
 
 /**
 Drive database that hosts its entries and allows to search for relevant data.
-
-USB entries are currently not supported.
 */
 #[derive(Debug)]
 pub struct DriveDB {
 	entries: Vec<Entry>,
+	// details of entry warnings, in the same order as `entries`
+	warnings: Vec<Option<warning::Inferred>>,
 
 	// pre-found default entry: most likely it will be used right away, so it's not that harmful,
 	// and it's better to have one if it's going to be requested multiple times
@@ -23,52 +29,210 @@ pub struct DriveDB {
 	// and are a must if multiple lookups are about to be performed
 	model_regexes: RegexSet,
 	firmware_regexes: RegexSet,
+
+	// USB bridge entries are matched against IDs rather than model/firmware strings, so they have their own sets
+	usb: Vec<UsbEntry>,
+	usb_id_regexes: RegexSet,
+	usb_bcd_regexes: RegexSet,
+
+	// files entries were loaded from, for cache invalidation
+	sources: Vec<Source>,
 }
 
 impl DriveDB {
-	pub(crate) fn new(entries: Vec<Entry>) -> Result<Self, regex::Error> {
-		let entries = entries.into_iter()
-			// USB ID entries are parsed differently; also, we don't support USB devices yet
-			.filter(|e| ! e.model.starts_with("USB:"));
+	pub(crate) fn new(entries: Vec<Entry>, sources: Vec<Source>) -> Result<Self, loader::Error> {
+		// USB ID entries are parsed and matched differently
+		let (usb, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(UsbEntry::is_usb);
+		let usb: Vec<_> = usb.into_iter().map(UsbEntry::new).collect();
 
 		// filter out all entries marked as default: they're of no use fo self.find()
 		// (yes, there might be multiple default entries from e.g. additional drivedb files)
-		let (default, entries): (Vec<_>, Vec<_>) = entries.partition(|e| e.family == "DEFAULT");
+		let (default, entries): (Vec<_>, Vec<_>) = entries.into_iter().partition(|e| e.family == "DEFAULT");
 
 		// pick the first default entry, if any, or set to None
 		let default = default.into_iter().next();
 
-		// model and firmware are expected to be ascii strings, no need to try matching unicode characters
-		// hence `unicode(false)` and use of `regex::bytes::*` instead of `regex::*`
-		let model_regexes = RegexSetBuilder::new(entries.iter()
+		let model_regexes = regex_set(entries.iter()
 			.map(|e| format!("^{}$", e.model))
-		).unicode(false).build()?;
-		let firmware_regexes = RegexSetBuilder::new(entries.iter()
-			.map(|e|
-				if e.firmware.is_empty() {
-					"".to_string()
-				} else {
-					format!("^{}$", e.firmware)
-				}
-			)
-		).unicode(false).build()?;
+			.collect()
+		)?;
+		let firmware_regexes = regex_set(entries.iter()
+			.map(|e| optional_regex(&e.firmware))
+			.collect()
+		)?;
+
+		let usb_id_regexes = regex_set(usb.iter()
+			.map(|e| format!("^{}$", e.entry.model))
+			.collect()
+		)?;
+		let usb_bcd_regexes = regex_set(usb.iter()
+			.map(|e| optional_regex(&e.entry.firmware))
+			.collect()
+		)?;
+
+		let warnings = warning::Inferred::all(&entries);
 
 		Ok(DriveDB {
 			entries,
+			warnings,
 			default,
 			model_regexes,
 			firmware_regexes,
+			usb,
+			usb_id_regexes,
+			usb_bcd_regexes,
+			sources,
 		})
 	}
 
-	fn find(&self, model: &str, firmware: &str) -> Option<&Entry> {
+	/**
+	Saves all entries into the cache `file` that can later be loaded with [`Loader::load_cache()`](struct.Loader.html#method.load_cache).
+
+	Cache is replaced atomically, and it remembers which files entries were loaded from (or hashes of databases loaded from memory), so it is safe to call this every time the database was loaded from the original files.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if it encounters any kind of I/O error.
+	*/
+	pub fn save_cache(&self, file: &str) -> Result<(), loader::Error> {
+		let entries: Vec<_> = self.default.iter()
+			.chain(self.entries.iter())
+			.chain(self.usb.iter().map(|e| &e.entry))
+			.collect();
+		cache::save(file, &self.sources, &entries)
+	}
+
+	fn matches(&self, model: &str, firmware: &str) -> Vec<usize> {
 		let models: HashSet<_> = self.model_regexes.matches(model.as_bytes()).iter().collect();
 		let firmwares: HashSet<_> = self.firmware_regexes.matches(firmware.as_bytes()).iter().collect();
 
+		let mut matches: Vec<_> = models.intersection(&firmwares).cloned().collect();
+		matches.sort();
+		matches
+	}
+
+	fn find(&self, model: &str, firmware: &str) -> Option<usize> {
 		// find the first match (if any)
-		models.intersection(&firmwares)
-			.min()
-			.map(|&index| &self.entries[index])
+		self.matches(model, firmware).first().cloned()
+	}
+
+	/**
+	Returns all entries that match given `model` and `firmware`, in the order they appear in the database (that is, entries from additional files go first).
+
+	Only the first of them is used by [`render_meta()`](#method.render_meta); the rest are shadowed by it, which might come in handy when debugging the database. The default entry is never returned.
+	*/
+	pub fn find_all(&self, model: &str, firmware: &str) -> Vec<&Entry> {
+		self.matches(model, firmware).into_iter()
+			.map(|index| &self.entries[index])
+			.collect()
+	}
+
+	/// Iterates over drive entries, in the order they are matched against (that is, entries from additional files go first). The default entry is not included.
+	pub fn entries(&self) -> slice::Iter<'_, Entry> {
+		self.entries.iter()
+	}
+
+	/// Iterates over USB bridge entries, in the order they are matched against.
+	pub fn usb_entries(&self) -> slice::Iter<'_, UsbEntry> {
+		self.usb.iter()
+	}
+
+	/// Returns drive entries with model family containing `family` (case-insensitive).
+	pub fn find_by_family(&self, family: &str) -> Vec<&Entry> {
+		let family = family.to_lowercase();
+		self.entries.iter()
+			.filter(|e| e.family.to_lowercase().contains(&family))
+			.collect()
+	}
+
+	/// Returns drive entries with model regex matching given `model`, regardless of their firmware regex.
+	pub fn find_by_model_regex(&self, model: &str) -> Vec<&Entry> {
+		// set matches are always yielded in ascending order
+		self.model_regexes.matches(model.as_bytes()).into_iter()
+			.map(|index| &self.entries[index])
+			.collect()
+	}
+
+	/**
+	Looks for the USB bridge with given vendor and product IDs, and, if known, device release number (`bcdDevice`).
+
+	Like with drives, if multiple entries match, the first one is used. Entries with empty `bcdDevice` regex match any release number; entries limited to particular releases are only used if `bcd_device` is unknown or matches their regex.
+
+	```
+	let mut loader = drivedb::Loader::new();
+	loader.load_from_str(r#"
+		{ "USB: ; JMicron JM20329", "0x152d:0x2329", "0x0100", "", "-d usbjmicron" },
+		{ "USB: ; JMicron JM20329", "0x152d:0x2329", "0x0200", "", "-d sat" },
+	"#).unwrap();
+	let db = loader.db().unwrap();
+
+	let protocol = |bcd| db.match_usb(0x152d, 0x2329, bcd)
+		.and_then(|usb| usb.device_type.as_ref())
+		.map(|t| t.protocol.as_str());
+	assert_eq!(protocol(Some(0x0200)), Some("sat"));
+	assert_eq!(protocol(Some(0x0300)), None); // unknown release
+	assert_eq!(protocol(None), Some("usbjmicron"));
+	assert!(db.match_usb(0x152d, 0x2330, None).is_none());
+	```
+	*/
+	pub fn match_usb(&self, vendor_id: u16, product_id: u16, bcd_device: Option<u16>) -> Option<&UsbEntry> {
+		let id = format!("0x{:04x}:0x{:04x}", vendor_id, product_id);
+		let ids: HashSet<_> = self.usb_id_regexes.matches(id.as_bytes()).iter().collect();
+
+		let index = match bcd_device {
+			Some(bcd) => {
+				let bcd = format!("0x{:04x}", bcd);
+				let bcds: HashSet<_> = self.usb_bcd_regexes.matches(bcd.as_bytes()).iter().collect();
+				// entries with empty bcdDevice regex are in `bcds` as well
+				ids.intersection(&bcds).min().cloned()
+			},
+			// entries with bcdDevice regex are still applicable if the release number is unknown
+			None => ids.into_iter().min(),
+		};
+
+		index.map(|index| &self.usb[index])
+	}
+
+	/**
+	Returns attribute descriptions from the default entry for a drive of given type, as they would be rendered with [`DriveMeta::render_attribute()`](struct.DriveMeta.html#method.render_attribute) for a drive that matched no other entry.
+
+	Only attributes that are explicitly mentioned in the default entry are listed; descriptions that apply to all attributes at once (`-v N,…`) are merged into every one of them.
+	*/
+	pub fn default_attributes(&self, drivetype: Option<Type>) -> BTreeMap<u8, Attribute> {
+		let presets = self.default.as_ref()
+			.and_then(|default| presets::parse(&default.presets))
+			.unwrap_or_default();
+		render_attributes(&filter_presets(drivetype, presets))
+	}
+
+	/**
+	Checks all entries (including the default one and USB bridges) for problems that are otherwise silently ignored while matching drives, e.g. to lint custom drivedb additions before deploying them.
+
+	The following is checked:
+
+	- model regex is not empty,
+	- presets only contain known options, and each option has an argument,
+	- attribute descriptions (`-v`) are valid, that is, attribute ids are within 1..255, formats are known, byte orders are well-formed, etc. (see [`vendor_attribute::parse()`](vendor_attribute/fn.parse.html)),
+	- firmware bug workarounds (`-F`) are known to smartmontools.
+
+	Regexes are not checked here: database with invalid regexes cannot be [loaded](struct.Loader.html#method.db) in the first place.
+	*/
+	pub fn validate(&self) -> Vec<Issue<'_>> {
+		let mut issues = vec![];
+
+		let entries = self.default.iter()
+			.chain(self.entries.iter())
+			.chain(self.usb.iter().map(|e| &e.entry));
+		for entry in entries {
+			if entry.model.is_empty() {
+				issues.push(Issue { entry, message: "model regex is empty".to_string() });
+			}
+			issues.extend(presets::validate(&entry.presets).into_iter()
+				.map(|message| Issue { entry, message })
+			);
+		}
+
+		issues
 	}
 
 	/**
@@ -81,7 +245,9 @@ impl DriveDB {
 		let mut m = DriveMeta {
 			family: None,
 			warning: None,
+			recommended_type: None,
 			presets: Vec::<Attribute>::new(),
+			entry: None,
 		};
 
 		// TODO show somehow whether default entry was found or not, or ask caller for the default entry
@@ -92,14 +258,17 @@ impl DriveDB {
 			}
 		}
 
-		if let Some(entry) = self.find(model, firmware) {
+		if let Some(index) = self.find(model, firmware) {
+			let entry = &self.entries[index];
 			// TODO show somehow whether preset is valid or not
 			if let Some(presets) = presets::parse(&entry.presets) {
 				m.presets.extend(presets);
 			}
 
 			m.family = Some(&entry.family);
-			m.warning = if ! entry.warning.is_empty() { Some(&entry.warning) } else { None };
+			m.warning = self.warnings[index].as_ref().map(|inferred| Warning::new(entry, inferred));
+			m.recommended_type = presets::device_type(&entry.presets);
+			m.entry = Some(entry);
 		}
 
 		m.presets.extend(extra_attributes.iter().map(|a| a.clone()));
@@ -108,6 +277,30 @@ impl DriveDB {
 	}
 }
 
+fn optional_regex(re: &str) -> String {
+	// empty regexes match anything
+	if re.is_empty() {
+		"".to_string()
+	} else {
+		format!("^{}$", re)
+	}
+}
+
+fn regex_set(patterns: Vec<String>) -> Result<RegexSet, loader::Error> {
+	// model and firmware are expected to be ascii strings, no need to try matching unicode characters
+	// hence `unicode(false)` and use of `regex::bytes::*` instead of `regex::*`
+	RegexSetBuilder::new(&patterns).unicode(false).build().map_err(|err| {
+		// RegexSet does not tell which pattern is to blame, so let's look for it ourselves;
+		// the culprit might also be the set as a whole (e.g. if it exceeds size limits)
+		patterns.iter()
+			.filter_map(|p| RegexBuilder::new(p).unicode(false).build().err()
+				.map(|err| loader::Error::Regex(Some(p.clone()), err))
+			)
+			.next()
+			.unwrap_or(loader::Error::Regex(None, err))
+	})
+}
+
 fn filter_presets(drivetype: Option<Type>, preset: Vec<Attribute>) -> Vec<Attribute> {
 	#[cfg_attr(feature = "cargo-clippy", allow(match_same_arms))]
 	preset.into_iter().filter(|attr| match (attr.drivetype, drivetype) {
@@ -122,51 +315,230 @@ fn filter_presets(drivetype: Option<Type>, preset: Vec<Attribute>) -> Vec<Attrib
 	}).collect()
 }
 
+/// Problem found in the database entry by [`DriveDB::validate()`](struct.DriveDB.html#method.validate)
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Issue<'a> {
+	/// entry the problem was found in
+	pub entry: &'a Entry,
+	/// what is wrong with it
+	pub message: String,
+}
+
+impl<'a> fmt::Display for Issue<'a> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if let Some(ref origin) = self.entry.origin {
+			write!(f, "{}: ", origin)?;
+		}
+		write!(f, "{} ({:?}): {}", self.entry.family, self.entry.model, self.message)
+	}
+}
+
 /// Drive-related data that cannot be queried from the drive itself (model family, attribute presets etc.)
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
 pub struct DriveMeta<'a> {
 	/// > Informal string about the model family/series of a device.
 	pub family: Option<&'a String>,
 
 	/// > A message that may be displayed for matching drives.
 	/// > For example, to inform the user that they may need to apply a firmware patch.
-	pub warning: Option<&'a String>,
+	pub warning: Option<Warning<'a>>,
 
-	/// SMART attribute descriptions
+	/// Device type the database recommends for this drive (e.g. if it is known to only work with some particular pass-through)
+	pub recommended_type: Option<RecommendedType>,
+
+	/// SMART attribute descriptions; serialized as rendered ones, see [`render_attributes()`](#method.render_attributes)
+	#[cfg_attr(feature = "serde", serde(rename = "attributes", serialize_with = "serialize_attributes"))]
 	presets: Vec<Attribute>,
+
+	// matched entry, if any (serialized as is, so that its regexes and origin are available too)
+	entry: Option<&'a Entry>,
 }
 
 impl<'a> DriveMeta<'a> {
-	/*
-	Attributes are never looked up; they must be rendered for a number of reasons:
-	- description might match all attributes at once (`-v N,…`, represented with `attr.id` of `None`),
-	- description might only update data format, leaving previously defined name and drive type intact.
+	/// Returns model family of the matched entry (shown as "Model Family" by smartctl), if any drive entry matched.
+	pub fn family(&self) -> Option<&'a str> {
+		self.family.map(|f| f.as_str())
+	}
+
+	/// Returns model regex of the matched entry, if any.
+	pub fn matched_model_regex(&self) -> Option<&'a str> {
+		self.entry.map(|e| e.model.as_str())
+	}
+
+	/**
+	Tells where the matched entry comes from, if known, which is handy for tracking down surprising matches.
+
+	```no_run
+	# let db = drivedb::Loader::new().db().unwrap();
+	let meta = db.render_meta("ST3000DM001-9YN166", "CC24", None, &vec![]);
+	if let Some(origin) = meta.origin() {
+		println!("matched {:?} at {}", meta.matched_model_regex().unwrap(), origin); // e.g. /usr/share/smartmontools/drivedb.h:1234
+	}
+	```
+	*/
+	pub fn origin(&self) -> Option<&'a Origin> {
+		self.entry.and_then(|e| e.origin.as_ref())
+	}
+
+	/**
+	Renders attribute description for a particular attribute `id`.
+
+	Use [`normalized_direction()`](vendor_attribute/struct.Attribute.html#method.normalized_direction) and [`raw_direction()`](vendor_attribute/struct.Attribute.html#method.raw_direction) of the result to tell which values are worse.
 	*/
-	/// Renders attribute description for a particular attribute `id`.
 	pub fn render_attribute(&'a self, id: u8) -> Option<Attribute> {
-		let mut out = None;
+		render_attribute(&self.presets, id)
+	}
 
-		for new in self.presets.iter() {
-			match new.id {
-				Some(x) if x != id => continue,
-				_ => ()
-			}
+	/**
+	Renders descriptions of all the attributes that are explicitly mentioned in the matched entry, the default entry, or extra attributes.
 
-			match out {
-				None => { out = Some(new.clone()); },
-				Some(ref mut old) => {
-					old.format = new.format.clone();
-					old.byte_order = new.byte_order.clone();
-					if new.name.is_some() {
-						old.name = new.name.clone();
-					}
-					if new.drivetype.is_some() {
-						old.drivetype = new.drivetype;
-					}
-				},
-			}
+	Descriptions that apply to all attributes at once (`-v N,…`) are merged into every one of them; use [`render_attribute()`](#method.render_attribute) for attributes that are not listed here.
+
+	```
+	let mut loader = drivedb::Loader::new();
+	loader.load_from_str(r#"
+		{ "DEFAULT", "-", "", "", "-v 9,minutes -v N,raw48:543210 -v 194,tempminmax" },
+		{ "Family", "Model", "", "", "-v 194,temp10x,Temperature_Celsius_x10" },
+	"#).unwrap();
+	let db = loader.db().unwrap();
+
+	let attrs = db.render_meta("Model", "", None, &vec![]).render_attributes();
+	assert_eq!(attrs.keys().collect::<Vec<_>>(), [&9, &194]);
+	assert_eq!(attrs[&9].name.as_deref(), Some("Power_On_Minutes"));
+	assert_eq!(attrs[&9].format, "raw48");
+	assert_eq!(attrs[&194].name.as_deref(), Some("Temperature_Celsius_x10"));
+	assert_eq!(attrs[&194].format, "temp10x");
+	```
+	*/
+	pub fn render_attributes(&self) -> BTreeMap<u8, Attribute> {
+		render_attributes(&self.presets)
+	}
+}
+
+#[cfg(feature = "serde")]
+fn serialize_attributes<S: serde::Serializer>(presets: &[Attribute], serializer: S) -> Result<S::Ok, S::Error> {
+	serde::Serialize::serialize(&render_attributes(presets), serializer)
+}
+
+/*
+Attributes are never looked up; they must be rendered for a number of reasons:
+- description might match all attributes at once (`-v N,…`, represented with `attr.id` of `None`),
+- description might only update data format, leaving previously defined name and drive type intact.
+*/
+fn render_attribute(presets: &[Attribute], id: u8) -> Option<Attribute> {
+	let mut out = None;
+
+	for new in presets.iter() {
+		match new.id {
+			Some(x) if x != id => continue,
+			_ => ()
 		}
 
-		out
+		match out {
+			// descriptions of all attributes (`-v N,…`) that come first would otherwise leave the id unset
+			None => { out = Some(Attribute { id: Some(id), ..new.clone() }); },
+			Some(ref mut old) => {
+				old.format = new.format.clone();
+				old.byte_order = new.byte_order.clone();
+				old.increasing = new.increasing;
+				if new.name.is_some() {
+					old.name = new.name.clone();
+				}
+				if new.drivetype.is_some() {
+					old.drivetype = new.drivetype;
+				}
+			},
+		}
+	}
+
+	out
+}
+
+fn render_attributes(presets: &[Attribute]) -> BTreeMap<u8, Attribute> {
+	presets.iter()
+		.filter_map(|attr| attr.id)
+		.filter_map(|id| render_attribute(presets, id).map(|attr| (id, attr)))
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::Loader;
+
+	#[test]
+	fn render_attribute_id() {
+		let mut loader = Loader::new();
+		loader.load_from_str(r#"
+			{ "DEFAULT", "-", "", "", "-v N,raw48:543210 -v 194,tempminmax" },
+			{ "Family", "Model", "", "", "" },
+		"#).unwrap();
+		let db = loader.db().unwrap();
+		let meta = db.render_meta("Model", "", None, &vec![]);
+
+		let attr = meta.render_attribute(194).unwrap();
+		assert_eq!(attr.id, Some(194));
+		assert_eq!(attr.format, "tempminmax");
+		// only described as one of all the attributes
+		let attr = meta.render_attribute(5).unwrap();
+		assert_eq!(attr.id, Some(5));
+		assert_eq!(attr.format, "raw48");
+
+		assert_eq!(meta.render_attributes()[&194].id, Some(194));
+		assert_eq!(db.default_attributes(None)[&194].id, Some(194));
+	}
+
+	#[test]
+	fn validate() {
+		let mut loader = Loader::new();
+		loader.load_from_str(r#"
+			{ "Valid", "A", "", "", "-v 9,minutes -v N,raw48:543210 -F samsung2 -d sat" },
+			{ "Empty model", "", "", "", "" },
+			{ "Invalid id", "B", "", "", "-v 0,raw48" },
+			{ "Unknown format", "C", "", "", "-v 9,raw49" },
+			{ "Unknown bug", "D", "", "", "-F bogus" },
+			{ "Unknown option", "E", "", "", "-x 1" },
+			{ "No argument", "F", "", "", "-v 9,minutes -v" },
+		"#).unwrap();
+		let db = loader.db().unwrap();
+
+		let issues = db.validate();
+		let messages: Vec<_> = issues.iter()
+			.map(|issue| (issue.entry.family.as_str(), issue.message.as_str()))
+			.collect();
+		assert_eq!(messages, [
+			("Empty model", "model regex is empty"),
+			("Invalid id", r#"Unable to parse vendor attribute "0,raw48""#),
+			("Unknown format", r#"Unable to parse vendor attribute "9,raw49""#),
+			("Unknown bug", r#"unknown firmware bug "bogus""#),
+			("Unknown option", "unknown option -x"),
+			("No argument", "option -v lacks an argument"),
+		]);
+
+		assert_eq!(issues[0].to_string(), r#"<memory>:3: Empty model (""): model regex is empty"#);
+	}
+
+	#[test]
+	fn match_usb_any_release() {
+		let mut loader = Loader::new();
+		loader.load_from_str(r#"
+			{ "USB: ; JMicron JM20329", "0x152d:0x2329", "0x0100", "", "-d usbjmicron" },
+			{ "USB: Enclosure ; JMicron", "0x152d:0x2329", "", "", "-d sat" },
+			{ "USB: ; JMicron JM20336", "0x152d:0x2336", "0x0100", "", "-d usbjmicron,x" },
+		"#).unwrap();
+		let db = loader.db().unwrap();
+
+		let device_type = |product, bcd| db.match_usb(0x152d, product, bcd)
+			.and_then(|usb| usb.device_type.as_ref())
+			.map(|t| t.to_string());
+
+		assert_eq!(device_type(0x2329, Some(0x0100)).as_deref(), Some("usbjmicron"));
+		// falls through to the entry that is not limited to particular releases
+		assert_eq!(device_type(0x2329, Some(0x0200)).as_deref(), Some("sat"));
+		assert_eq!(device_type(0x2329, None).as_deref(), Some("usbjmicron"));
+		// there's no such entry for this one
+		assert_eq!(device_type(0x2336, Some(0x0200)), None);
+		assert_eq!(device_type(0x2336, None).as_deref(), Some("usbjmicron,x"));
 	}
 }
@@ -5,7 +5,9 @@ Use this crate to match hard drive and SMART values it returns against smartmont
 
 ```
 use drivedb::{
+	Direction,
 	Loader,
+	Severity,
 	Type,
 	vendor_attribute,
 };
@@ -32,8 +34,9 @@ let drivetype = Some(Type::HDD);
 
 let meta = db.render_meta(&model, &firmware, drivetype, &user_attributes);
 
-assert!(meta.warning.is_some());
-assert!(meta.warning.unwrap().starts_with("A firmware update for this drive may be available"));
+let warning = meta.warning.as_ref().unwrap();
+assert!(warning.text.starts_with("A firmware update for this drive may be available"));
+assert_eq!(warning.severity, Severity::FirmwareUpdate);
 
 let attr = meta.render_attribute(9).unwrap();
 assert_eq!(attr.id, Some(9));
@@ -41,10 +44,22 @@ assert_eq!(attr.name, Some("Power_On_Minutes".to_string()));
 assert_eq!(attr.format, "min2hour".to_string());
 assert_eq!(attr.byte_order, "543210".to_string());
 assert_eq!(attr.drivetype, None);
+assert_eq!(attr.normalized_direction(), Some(Direction::LowerIsWorse));
+assert_eq!(attr.raw_direction(), None);
 
 # Ok(())
 # }
 ```
+
+## WebAssembly
+
+This crate can be built for `wasm32-unknown-unknown`, e.g. to match drives in a browser. There's no file system there, so use [`Loader::load_from_str()`](struct.Loader.html#method.load_from_str) to load the database.
+
+## Features
+
+* `serde`: derive `Serialize` (and `Deserialize`, where applicable) for public data structures like [`Attribute`](vendor_attribute/struct.Attribute.html) and [`DriveMeta`](struct.DriveMeta.html).
+* `json`: allow [`Loader`](struct.Loader.html) to read drive databases exported to JSON.
+* `drivedb-update`: allow [`Loader`](struct.Loader.html) to download drivedb.h from smartmontools repository, [pinned to a known checksum](struct.Loader.html#method.fetch_pinned).
 */
 
 #![warn(
@@ -65,11 +80,32 @@ extern crate quick_error;
 extern crate nom;
 extern crate regex;
 
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "drivedb-update")]
+extern crate ureq;
+#[cfg(feature = "drivedb-update")]
+extern crate sha2;
+
 mod parser;
 mod presets;
 mod drivedb;
+mod usb;
+mod warning;
 mod loader;
+mod cache;
+#[cfg(feature = "drivedb-update")]
+mod update;
 pub mod vendor_attribute;
-pub use self::vendor_attribute::{Attribute, Type};
-pub use self::drivedb::{DriveDB, DriveMeta};
+pub use self::vendor_attribute::{Attribute, Direction, Type};
+pub use self::drivedb::{DriveDB, DriveMeta, Issue};
+pub use self::parser::{Entry, Origin, Position, SyntaxError};
+pub use self::usb::UsbEntry;
+pub use self::presets::RecommendedType;
+pub use self::warning::{Warning, Severity};
 pub use self::loader::{Loader, Error};
+#[cfg(feature = "drivedb-update")]
+pub use self::update::UPSTREAM;
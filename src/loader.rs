@@ -1,61 +1,170 @@
-use super::parser::{self, Entry};
+use super::parser::{self, Entry, SyntaxError};
 use super::DriveDB;
+use super::cache::{self, Source};
 
-use std::fs::File;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io;
 
-use nom;
-
 use regex; // for Loader.db() error type
 
+#[cfg(feature = "json")]
+use serde_json;
+
+#[cfg(feature = "drivedb-update")]
+use ureq;
+#[cfg(feature = "drivedb-update")]
+use super::update;
+
 quick_error! {
 	#[derive(Debug)]
 	#[allow(missing_docs)] // self-explanatory
 	pub enum Error {
-		IO(err: io::Error) {
-			from()
-			display("IO error: {}", err)
-			description(err.description())
-			cause(err)
+		IO(file: String, err: io::Error) {
+			display("{}: IO error: {}", file, err)
+			source(err)
+		}
+		Parse(file: String, err: SyntaxError) {
+			display("{}:{}", file, err)
+			source(err)
+		}
+		/// Cache file is malformed or was written by an incompatible version of this crate
+		Cache(file: String) {
+			display("{}: unable to read the cache", file)
+		}
+		/// Files cached entries were loaded from have changed since the cache was written
+		StaleCache(file: String) {
+			display("{}: cache is outdated", file)
 		}
-		Parse {
-			// TODO? Parse(nom::verbose_errors::Err) if dependencies.nom.features = ["verbose-errors"]
-			display("Unable to parse the drivedb")
-			description("malformed database")
+		#[cfg(feature = "drivedb-update")]
+		Download(url: String, err: Box<ureq::Error>) {
+			display("unable to download the drivedb: {}", err) // ureq errors already mention the url
+			source(err)
+		}
+		/// Downloaded file does not match the expected SHA-256 checksum
+		#[cfg(feature = "drivedb-update")]
+		Checksum(url: String, expected: String, actual: String) {
+			display("{}: checksum mismatch: expected {}, got {}", url, expected, actual)
+		}
+		#[cfg(feature = "json")]
+		JSON(file: String, err: serde_json::Error) {
+			display("{}: unable to parse the drivedb: {}", file, err)
+			source(err)
+		}
+		/// Model or firmware regexes cannot be compiled; `pattern` is the offending regex, if it can be pinpointed
+		Regex(pattern: Option<String>, err: regex::Error) {
+			display("invalid regex{}: {}", pattern.as_ref().map(|p| format!(" {:?}", p)).unwrap_or_default(), err)
+			source(err)
 		}
 	}
 }
 
-fn load(file: &str) -> Result<Vec<Entry>, Error> {
+// used in place of file names in errors for databases that were not loaded from files
+const IN_MEMORY: &str = "<memory>";
+
+pub(crate) fn read(file: &str) -> Result<Vec<u8>, Error> {
 	let mut db = Vec::new();
-	File::open(&file)?.read_to_end(&mut db)?;
+	File::open(&file)
+		.and_then(|mut f| f.read_to_end(&mut db))
+		.map_err(|err| Error::IO(file.to_string(), err))?;
+	Ok(db)
+}
 
-	match parser::database(&db) {
-		nom::IResult::Done(_, entries) => Ok(entries),
-		nom::IResult::Error(_) => Err(Error::Parse),
-		nom::IResult::Incomplete(_) => unreachable!(), // XXX is it true?
+pub(crate) fn write(file: &str, data: &[u8]) -> Result<(), Error> {
+	// write to a temporary file first so that the existing copy is never left half-written
+	let tmp = format!("{}.new", file);
+	fs::write(&tmp, data)
+		.and_then(|_| fs::rename(&tmp, file))
+		.map_err(|err| Error::IO(file.to_string(), err))
+}
+
+// Files are expected to be stat'ed *before* they are read, and the loader to be updated only after both succeed:
+// that way, if the file changes in between, the cache built from its entries is considered outdated rather than fresh.
+fn source(file: &str) -> Result<Source, Error> {
+	Source::new(file).map_err(|err| Error::IO(file.to_string(), err))
+}
+
+/// Parses the database, returning its entries along with problems that were tolerated as `Error::Parse`
+pub(crate) fn parse(file: &str, db: &[u8], lenient: bool) -> Result<(Vec<Entry>, Vec<Error>), Error> {
+	let (mut entries, warnings) = parser::database(db, lenient)
+		.map_err(|err| Error::Parse(file.to_string(), err))?;
+	for entry in entries.iter_mut() {
+		if let Some(ref mut origin) = entry.origin {
+			origin.file = file.to_string();
+		}
 	}
+	let warnings = warnings.into_iter()
+		.map(|err| Error::Parse(file.to_string(), err))
+		.collect();
+	Ok((entries, warnings))
+}
+
+fn load(file: &str, lenient: bool) -> Result<(Vec<Entry>, Vec<Error>), Error> {
+	parse(file, &read(file)?, lenient)
+}
+
+#[cfg(feature = "json")]
+fn load_json(file: &str) -> Result<Vec<Entry>, Error> {
+	let db = read(file)?;
+
+	let entries: Vec<Entry> = serde_json::from_slice(&db)
+		.map_err(|err| Error::JSON(file.to_string(), err))?;
+	Ok(entries.into_iter().filter(Entry::is_enabled).collect())
 }
 
 /**
 Use this helper to load entries from `drivedb.h`.
 
 It is also possible to use `Loader` to create dummy database in case if only user-defined attributes (or none at all) are needed: just call [`db()`](#method.db) right after creating the loader.
+
+## Precedence
+
+Entries are matched in the following order, and the first match wins:
+
+- entries from additional files, in the order these files were loaded,
+- entries from the main file.
+
+This mirrors smartctl's `-B` option: `-B +FILE` corresponds to [`load_additional()`](#method.load_additional), and `-B FILE` (which replaces the default database) to [`load()`](#method.load).
+Use [`overrides()`](#method.overrides) to find out which entries are shadowed this way.
 */
 #[derive(Debug)]
 pub struct Loader {
 	entries: Vec<Entry>,
 	additional: Vec<Entry>,
+	sources: Vec<Source>,
+	additional_sources: Vec<Source>,
+	lenient: bool,
+	warnings: Vec<Error>,
 }
 impl Loader {
 	pub fn new() -> Self {
 		Loader {
 			entries: vec![],
 			additional: vec![],
+			sources: vec![],
+			additional_sources: vec![],
+			lenient: false,
+			warnings: vec![],
 		}
 	}
 	/**
+	Sets whether malformed entries of drivedb.h files loaded afterwards should be skipped instead of failing the whole file.
+
+	Skipped entries are reported in [`warnings()`](#method.warnings).
+	*/
+	pub fn set_lenient(&mut self, lenient: bool) {
+		self.lenient = lenient;
+	}
+	/**
+	Returns problems found in files loaded so far that did not prevent them from being loaded, like unknown entry fields, or malformed entries if the loader [is lenient](#method.set_lenient).
+
+	These are always reported as [`Error::Parse`](enum.Error.html#variant.Parse).
+	*/
+	pub fn warnings(&self) -> &[Error] {
+		&self.warnings
+	}
+	/**
 	Loads entries from main drivedb file.
 
 	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
@@ -68,7 +177,115 @@ impl Loader {
 	- drive database is malformed.
 	*/
 	pub fn load(&mut self, file: &str) -> Result<(), Error> {
-		self.entries = load(file)?;
+		let source = source(file)?;
+		let (entries, mut warnings) = load(file, self.lenient)?;
+		self.entries = entries;
+		self.sources = vec![source];
+		self.warnings.append(&mut warnings);
+		Ok(())
+	}
+	/**
+	Loads entries of main drive database from `db` string, e.g. one embedded into the program with `include_str!()`, or pasted by the user.
+
+	This is the way to go on platforms without file system, like `wasm32-unknown-unknown`.
+
+	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if drive database is malformed.
+	*/
+	pub fn load_from_str(&mut self, db: &str) -> Result<(), Error> {
+		let (entries, mut warnings) = parse(IN_MEMORY, db.as_bytes(), self.lenient)?;
+		self.entries = entries;
+		self.sources = vec![Source::memory(db.as_bytes())];
+		self.warnings.append(&mut warnings);
+		Ok(())
+	}
+	/**
+	Loads entries of main drive database from `reader`, e.g. a network stream or an archive member.
+
+	Like with [`load_from_str()`](#method.load_from_str), there's no file to keep track of, so use [`load_cache_for()`](#method.load_cache_for) to load caches built from these entries.
+
+	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if:
+
+	- it encounters any kind of I/O error,
+	- drive database is malformed.
+	*/
+	pub fn load_from_reader<R: Read>(&mut self, mut reader: R) -> Result<(), Error> {
+		let mut db = Vec::new();
+		reader.read_to_end(&mut db)
+			.map_err(|err| Error::IO(IN_MEMORY.to_string(), err))?;
+
+		let (entries, mut warnings) = parse(IN_MEMORY, &db, self.lenient)?;
+		self.entries = entries;
+		self.sources = vec![Source::memory(&db)];
+		self.warnings.append(&mut warnings);
+		Ok(())
+	}
+	/**
+	Loads entries from main drivedb file exported to JSON.
+
+	The file is expected to contain an array of objects with the very same fields drivedb.h entries consist of:
+
+	```json
+	[
+		{
+			"family": "Seagate Barracuda 7200.14 (AF)",
+			"model": "ST3000DM001-.*",
+			"firmware": "CC4[679CH]",
+			"warning": "",
+			"presets": "-v 188,raw16 -v 240,msec24hour32"
+		}
+	]
+	```
+
+	`firmware`, `warning` and `presets` can be omitted if empty.
+
+	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if:
+
+	- it encounters any kind of I/O error,
+	- drive database is malformed.
+	*/
+	#[cfg(feature = "json")]
+	pub fn load_json(&mut self, file: &str) -> Result<(), Error> {
+		let source = source(file)?;
+		self.entries = load_json(file)?;
+		self.sources = vec![source];
+		Ok(())
+	}
+	/**
+	Downloads drivedb.h at particular `revision` (e.g. commit hash or release tag) of smartmontools repository hosted at `url`, checks it against expected `sha256` checksum (hex-encoded), saves it to `cache` and loads entries from it as if it was the main drivedb file.
+
+	GPG signatures are not checked, so unlike `update-smart-drivedb(8)`, there is no way to fetch whatever revision is the latest one: the checksum is what guarantees the database is exactly the one the caller expects, e.g. one that was reviewed beforehand.
+	Cached copy is replaced atomically and only after these checks pass, so it is always safe to [`load()`](#method.load) it later, e.g. if fetching fails.
+
+	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if:
+
+	- file cannot be downloaded,
+	- checksum does not match,
+	- drive database is malformed,
+	- cached copy cannot be written.
+	*/
+	#[cfg(feature = "drivedb-update")]
+	pub fn fetch_pinned(&mut self, url: &str, revision: &str, cache: &str, sha256: &str) -> Result<(), Error> {
+		let (entries, mut warnings) = update::fetch(url, revision, cache, sha256)?;
+		let source = source(cache)?;
+		self.entries = entries;
+		self.sources = vec![source];
+		self.warnings.append(&mut warnings);
 		Ok(())
 	}
 	/**
@@ -82,15 +299,287 @@ impl Loader {
 	- drive database is malformed.
 	*/
 	pub fn load_additional(&mut self, file: &str) -> Result<(), Error> {
-		self.additional.append(&mut load(file)?);
+		let source = source(file)?;
+		let (mut entries, mut warnings) = load(file, self.lenient)?;
+		self.additional.append(&mut entries);
+		self.additional_sources.push(source);
+		self.warnings.append(&mut warnings);
+		Ok(())
+	}
+	/**
+	Loads entries from the cache previously written with [`DriveDB::save_cache()`](struct.DriveDB.html#method.save_cache), as if they all came from the main drivedb file.
+
+	This is much faster than parsing drivedb.h, but the cache is only considered valid if none of the files it was built from were changed (judging by their size and modification time) since it was written.
+
+	Entries from previously loaded main file will be discarded; entries from additional files will not be affected.
+
+	## Errors
+
+	Returns [enum Error](enum.Error.html) if:
+
+	- it encounters any kind of I/O error,
+	- cache is malformed or was written by an incompatible version of this crate,
+	- cache is outdated; callers are expected to fall back to loading original files then.
+	*/
+	pub fn load_cache(&mut self, file: &str) -> Result<(), Error> {
+		let (sources, entries) = cache::load(file, None)?;
+		self.entries = entries;
+		self.sources = sources;
+		Ok(())
+	}
+	/**
+	Same as [`load_cache()`](#method.load_cache), but for caches built from databases loaded [from strings](#method.load_from_str) or [readers](#method.load_from_reader):
+	there's no file to check for changes, so the cache is only considered valid if it was built from exactly the same database `db`, e.g. the one embedded into the program with `include_str!()`.
+
+	Caches of such databases are always considered outdated by `load_cache()`.
+
+	```no_run
+	use drivedb::Loader;
+
+	const DB: &str = r#"{ "Family", "Model", "", "", "" },"#; // e.g. include_str!("drivedb.h")
+
+	# fn main() -> Result<(), drivedb::Error> {
+	let mut loader = Loader::new();
+	let db = match loader.load_cache_for("/var/cache/drivedb.cache", DB) {
+		Ok(()) => loader.db()?,
+		// cache is missing, or the program was upgraded along with the embedded database
+		Err(_) => {
+			loader.load_from_str(DB)?;
+			let db = loader.db()?;
+			db.save_cache("/var/cache/drivedb.cache")?;
+			db
+		},
+	};
+	# Ok(())
+	# }
+	```
+
+	## Errors
+
+	Same as for [`load_cache()`](#method.load_cache).
+	*/
+	pub fn load_cache_for<D: AsRef<[u8]>>(&mut self, file: &str, db: D) -> Result<(), Error> {
+		let (sources, entries) = cache::load(file, Some(db.as_ref()))?;
+		self.entries = entries;
+		self.sources = sources;
 		Ok(())
 	}
-	/// Returns actual drive database with all entries loaded beforehand.
-	pub fn db(self) -> Result<DriveDB, regex::Error> {
+	/**
+	Lists entries that will never be matched because an entry with exactly the same model and firmware regexes [takes precedence](#precedence) over them.
+
+	Each pair consists of the overriding entry and the overridden one, e.g. an entry from an additional file and its counterpart from the main file. Entries that merely overlap (e.g. `ST3000DM001-.*` and `ST3000DM.*`) are not reported.
+	*/
+	pub fn overrides(&self) -> Vec<(&Entry, &Entry)> {
+		let mut seen = HashMap::new();
+		let mut overrides = vec![];
+
+		for entry in self.additional.iter().chain(self.entries.iter()) {
+			let key = (entry.model.as_str(), entry.firmware.as_str());
+			match seen.get(&key) {
+				Some(&first) => overrides.push((first, entry)),
+				None => { seen.insert(key, entry); },
+			}
+		}
+
+		overrides
+	}
+	/**
+	Returns actual drive database with all entries loaded beforehand.
+
+	## Errors
+
+	Returns [`Error::Regex`](enum.Error.html#variant.Regex) if any of the entries carries a regex that cannot be compiled.
+	*/
+	pub fn db(self) -> Result<DriveDB, Error> {
 		let entries: Vec<_> = self.additional.into_iter()
-			.chain(self.entries.into_iter())
+			.chain(self.entries)
+			.collect();
+		let sources: Vec<_> = self.sources.into_iter()
+			.chain(self.additional_sources)
+			.collect();
+
+		DriveDB::new(entries, sources)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::env;
+	use std::process;
+
+	const DB: &str = r#"
+		{ "DEFAULT", "-", "", "", "-v 9,minutes" },
+		{ "Seagate Barracuda 7200.14 (AF)", "ST3000DM001-.*", "", "", "-v 188,raw16" },
+		{ "USB: ; JMicron JM20329", "0x152d:0x2329", "", "", "-d usbjmicron" },
+	"#;
+
+	// unique per test, as tests run in parallel
+	fn tmp(name: &str) -> String {
+		env::temp_dir()
+			.join(format!("drivedb-test-{}-{}", process::id(), name))
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	#[test]
+	fn cache_roundtrip() {
+		let (file, cache) = (tmp("roundtrip.h"), tmp("roundtrip.cache"));
+		fs::write(&file, DB).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load(&file).unwrap();
+		let db = loader.db().unwrap();
+		db.save_cache(&cache).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load_cache(&cache).unwrap();
+		let cached = loader.db().unwrap();
+
+		let summary = |db: &DriveDB| db.entries()
+			.map(|e| (e.family.clone(), e.model.clone(), e.presets.clone(), e.origin.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(summary(&cached), summary(&db));
+		assert_eq!(cached.usb_entries().count(), 1);
+
+		let meta = cached.render_meta("ST3000DM001-9YN166", "CC24", None, &vec![]);
+		assert_eq!(meta.family(), Some("Seagate Barracuda 7200.14 (AF)"));
+		assert_eq!(meta.origin().map(|o| (o.file.as_str(), o.position.line)), Some((file.as_str(), 3)));
+		// presets of the default entry survive as well
+		assert_eq!(meta.render_attribute(9).unwrap().format, "min2hour");
+
+		fs::remove_file(&file).unwrap();
+		fs::remove_file(&cache).unwrap();
+	}
+
+	#[test]
+	fn cache_staleness() {
+		let (file, cache) = (tmp("stale.h"), tmp("stale.cache"));
+		fs::write(&file, DB).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load(&file).unwrap();
+		loader.db().unwrap().save_cache(&cache).unwrap();
+
+		fs::write(&file, format!("{}{}", DB, r#"{ "Foo", "Bar", "", "", "" },"#)).unwrap();
+		match Loader::new().load_cache(&cache) {
+			Err(Error::StaleCache(ref f)) if *f == cache => (),
+			other => panic!("expected stale cache, got {:?}", other),
+		}
+
+		fs::remove_file(&file).unwrap();
+		match Loader::new().load_cache(&cache) {
+			Err(Error::StaleCache(_)) => (),
+			other => panic!("expected stale cache, got {:?}", other),
+		}
+
+		fs::write(&cache, b"garbage").unwrap();
+		match Loader::new().load_cache(&cache) {
+			Err(Error::Cache(_)) => (),
+			other => panic!("expected malformed cache, got {:?}", other),
+		}
+
+		fs::remove_file(&cache).unwrap();
+	}
+
+	#[test]
+	fn memory_cache() {
+		let cache = tmp("memory.cache");
+
+		let mut loader = Loader::new();
+		loader.load_from_str(DB).unwrap();
+		loader.db().unwrap().save_cache(&cache).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load_cache_for(&cache, DB).unwrap();
+		assert_eq!(loader.entries.len(), 3);
+		// in case the cache is saved again
+		assert_eq!(loader.sources, [Source::memory(DB.as_bytes())]);
+
+		// e.g. the program was upgraded along with the embedded database
+		match Loader::new().load_cache_for(&cache, format!("{}{}", DB, r#"{ "Foo", "Bar", "", "", "" },"#)) {
+			Err(Error::StaleCache(_)) => (),
+			other => panic!("expected stale cache, got {:?}", other),
+		}
+		// there's nothing to compare the cache against
+		match Loader::new().load_cache(&cache) {
+			Err(Error::StaleCache(_)) => (),
+			other => panic!("expected stale cache, got {:?}", other),
+		}
+
+		let mut loader = Loader::new();
+		loader.load_from_reader(DB.as_bytes()).unwrap();
+		loader.db().unwrap().save_cache(&cache).unwrap();
+		Loader::new().load_cache_for(&cache, DB).unwrap();
+
+		fs::remove_file(&cache).unwrap();
+	}
+
+	#[cfg(feature = "json")]
+	#[test]
+	fn json() {
+		let file = tmp("db.json");
+		fs::write(&file, r#"[
+			{ "family": "Full", "model": "A", "firmware": "1", "warning": "W", "presets": "-v 9,minutes" },
+			{ "family": "Minimal", "model": "B" },
+			{ "family": "$Disabled", "model": "C" },
+			{ "family": "With origin", "model": "D", "origin": { "file": "drivedb.h", "position": { "line": 1, "column": 1 } } }
+		]"#).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load_json(&file).unwrap();
+		let entries: Vec<_> = loader.entries.iter()
+			.map(|e| (e.family.as_str(), e.model.as_str(), e.firmware.as_str(), e.warning.as_str(), e.presets.as_str(), e.origin.is_none()))
+			.collect();
+		assert_eq!(entries, [
+			("Full", "A", "1", "W", "-v 9,minutes", true),
+			("Minimal", "B", "", "", "", true),
+			("With origin", "D", "", "", "", true),
+		]);
+		assert_eq!(loader.sources.len(), 1);
+
+		fs::remove_file(&file).unwrap();
+	}
+
+	#[test]
+	fn overrides() {
+		let (main, additional) = (tmp("overrides-main.h"), tmp("overrides-additional.h"));
+		fs::write(&main, r#"
+			{ "Main exact", "ST3000DM001-.*", "CC2.", "", "" },
+			{ "Main overlapping", "ST4000DM.*", "", "", "" },
+			{ "Main other firmware", "ST5000DM.*", "CC2.", "", "" },
+		"#).unwrap();
+		fs::write(&additional, r#"
+			{ "Additional exact", "ST3000DM001-.*", "CC2.", "", "" },
+			{ "Additional overlapping", "ST4000DM00[0-9]-.*", "", "", "" },
+			{ "Additional other firmware", "ST5000DM.*", "CC3.", "", "" },
+		"#).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load(&main).unwrap();
+		loader.load_additional(&additional).unwrap();
+		let overrides: Vec<_> = loader.overrides().into_iter()
+			.map(|(overriding, overridden)| (overriding.family.as_str(), overridden.family.as_str()))
 			.collect();
+		assert_eq!(overrides, [("Additional exact", "Main exact")]);
+
+		fs::remove_file(&main).unwrap();
+		fs::remove_file(&additional).unwrap();
+	}
+
+	#[test]
+	fn failed_load_keeps_previous_entries() {
+		let (file, missing) = (tmp("keep.h"), tmp("keep-missing.h"));
+		fs::write(&file, DB).unwrap();
+
+		let mut loader = Loader::new();
+		loader.load(&file).unwrap();
+		assert!(loader.load(&missing).is_err());
+		assert!(loader.load_additional(&missing).is_err());
+		assert_eq!(loader.entries.len(), 3);
+		assert_eq!(loader.sources.len(), 1);
+		assert!(loader.additional_sources.is_empty());
 
-		DriveDB::new(entries)
+		fs::remove_file(&file).unwrap();
 	}
 }
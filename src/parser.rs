@@ -1,72 +1,66 @@
-use nom::multispace;
-
-named!(comment_block, do_parse!(
-	tag!("/*") >>
-	take_until!("*/") >>
-	tag!("*/") >>
-	(&[])
-));
-
-named!(comment, do_parse!(
-	tag!("//") >>
-	take_until!("\n") >>
-	char!('\n') >>
-	(&[])
-));
-
-named!(whitespace, do_parse!(
-	many0!(alt!(
-		multispace | comment | comment_block
-	)) >>
-	(&[])
-));
-
-// TODO? \[bfav?0] \ooo \xhh
-named!(string_escaped_char <char>, do_parse!(
-	char!('\\') >>
-	s: map!(one_of!("\\\"'nrt"), |c| match c {
-		'\\' => '\\',
-		'"' => '"',
-		'\'' => '\'',
-		'n' => '\n',
-		'r' => '\r',
-		't' => '\t',
-		_ => unreachable!(),
-	}) >>
-	(s)
-));
-
-named!(string_char <char>, alt!(
-	none_of!("\n\\\"")
-	| string_escaped_char
-));
-
-named!(string_literal <String>, do_parse!(
-	char!('\"') >>
-	s: map!(
-		many0!(string_char),
-		|s: Vec<char>| { s.into_iter().collect() }
-	) >>
-	char!('\"') >>
-	(s)
-));
-
-named!(string <String>, do_parse!(
-	s0: string_literal >>
-	ss: many0!(do_parse!(
-		whitespace >>
-		s: string_literal >>
-		(s)
-	)) >>
-	({
-		let mut s = s0.to_owned();
-		for i in ss { s.push_str(i.as_str()) }
-		s
-	})
-));
-
-/// drivedb.h entry
-#[derive(Debug)]
+/*
+drivedb.h is a C source file (or rather a fragment of it) with an array of structures made of string literals, like this:
+
+	{ "Family", // comments are allowed
+	  "Model regex" "can be split into several literals",
+	  "", "", ""
+	},
+
+This parser goes through the file byte by byte, keeping track of the position, so that every error points at the exact line and column it occurred at.
+
+Entries own their strings rather than borrowing from the file contents: most fields are single literals without escapes, so each of them is copied exactly once, the file buffer is dropped right after parsing, and entries can outlive it (e.g. in `DriveDB` or the cache).
+Presets are not parsed here at all; this is deferred until an entry actually matches some drive.
+*/
+
+use std::error;
+use std::fmt;
+
+/// Location within the drivedb file (both line and column start with 1; columns are counted in bytes)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[allow(missing_docs)] // self-explanatory
+pub struct Position {
+	pub line: usize,
+	pub column: usize,
+}
+
+/// Malformed drivedb entry or unexpected data in the drivedb file
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct SyntaxError {
+	/// where the problem was encountered
+	pub position: Position,
+	/// what went wrong
+	pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}: {}", self.position.line, self.position.column, self.message)
+	}
+}
+
+impl error::Error for SyntaxError {}
+
+/// Where the entry was loaded from
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Origin {
+	/// path of the drivedb file (or URL it was downloaded from, or `<memory>` for databases loaded from strings)
+	pub file: String,
+	/// position of the opening brace of the entry
+	pub position: Position,
+}
+
+impl fmt::Display for Origin {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}:{}", self.file, self.position.line)
+	}
+}
+
+/// drivedb.h entry, as is
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Entry {
 	/// > Informal string about the model family/series of a device.
 	pub family: String,
@@ -78,45 +72,389 @@ pub struct Entry {
 	/// > POSIX extended regular expression to match a devices's firmware.
 	///
 	/// Optional if "".
+	#[cfg_attr(feature = "serde", serde(default))]
 	pub firmware: String,
 
 	/// > A message that may be displayed for matching drives.
 	/// > For example, to inform the user that they may need to apply a firmware patch.
+	#[cfg_attr(feature = "serde", serde(default))]
 	pub warning: String,
 
 	/// > String with vendor-specific attribute ('-v') and firmware bug fix ('-F') options.
 	/// > Same syntax as in smartctl command line.
+	#[cfg_attr(feature = "serde", serde(default))]
 	pub presets: String,
+
+	/// Where the entry comes from, if known (entries loaded from JSON files lack this)
+	#[cfg_attr(feature = "serde", serde(skip_deserializing))]
+	pub origin: Option<Origin>,
 }
 
-named!(comma, do_parse!(whitespace >> char!(',') >> whitespace >> (&[])));
-
-named!(entry <Entry>, do_parse!(
-	char!('{') >> whitespace >>
-	family: string >> comma >>
-	model: string >> comma >>
-	firmware: string >> comma >>
-	warning: string >> comma >>
-	presets: string >> whitespace >>
-	char!('}') >>
-	(Entry {
-		family: family,
-		model: model,
-		firmware: firmware,
-		warning: warning,
-		presets: presets,
-	})
-));
-
-named!(pub database <Vec<Entry>>, do_parse!(
-	whitespace >>
-	entries: many1!(do_parse!(
-		e: entry >> comma >> (e)
-	)) >>
-	whitespace >>
-	eof!() >>
-	(entries.into_iter().filter(|entry| {
+impl Entry {
+	pub(crate) fn is_enabled(&self) -> bool {
 		// > The entry is ignored if [modelfamily] starts with a dollar sign.
-		!entry.family.starts_with('$')
-	}).collect())
-));
+		!self.family.starts_with('$')
+	}
+}
+
+struct Parser<'a> {
+	data: &'a [u8],
+	offset: usize,
+	position: Position,
+}
+
+impl<'a> Parser<'a> {
+	fn new(data: &'a [u8]) -> Self {
+		Parser {
+			data,
+			offset: 0,
+			position: Position { line: 1, column: 1 },
+		}
+	}
+
+	fn error<T>(&self, position: Position, message: &str) -> Result<T, SyntaxError> {
+		Err(SyntaxError {
+			position,
+			message: message.to_string(),
+		})
+	}
+
+	fn peek(&self) -> Option<u8> {
+		self.data.get(self.offset).cloned()
+	}
+
+	fn starts_with(&self, s: &[u8]) -> bool {
+		self.data[self.offset..].starts_with(s)
+	}
+
+	fn bump(&mut self) -> Option<u8> {
+		let c = self.peek()?;
+		self.offset += 1;
+		if c == b'\n' {
+			self.position.line += 1;
+			self.position.column = 1;
+		} else {
+			self.position.column += 1;
+		}
+		Some(c)
+	}
+
+	fn expect(&mut self, c: u8) -> Result<(), SyntaxError> {
+		if self.peek() == Some(c) {
+			self.bump();
+			Ok(())
+		} else {
+			let position = self.position;
+			self.error(position, &format!("expected '{}', found {}", c as char, self.describe_next()))
+		}
+	}
+
+	fn describe_next(&self) -> String {
+		match self.peek() {
+			None => "end of file".to_string(),
+			Some(c) if c.is_ascii_graphic() => format!("'{}'", c as char),
+			Some(c) => format!("byte 0x{:02x}", c),
+		}
+	}
+
+	/// skips whitespace and comments
+	fn whitespace(&mut self) -> Result<(), SyntaxError> {
+		loop {
+			match self.peek() {
+				Some(c) if c.is_ascii_whitespace() => { self.bump(); },
+				Some(b'/') if self.starts_with(b"//") => {
+					while let Some(c) = self.bump() {
+						if c == b'\n' { break }
+					}
+				},
+				Some(b'/') if self.starts_with(b"/*") => {
+					let start = self.position;
+					self.bump(); self.bump();
+					loop {
+						if self.starts_with(b"*/") {
+							self.bump(); self.bump();
+							break;
+						}
+						if self.bump().is_none() {
+							return self.error(start, "unterminated comment");
+						}
+					}
+				},
+				_ => return Ok(()),
+			}
+		}
+	}
+
+	// TODO? \[bfav?0] \ooo \xhh
+	fn string_literal(&mut self, out: &mut Vec<u8>) -> Result<(), SyntaxError> {
+		let start = self.position;
+		self.expect(b'"')?;
+		loop {
+			// copy plain characters in bulk, they never contain newlines anyway
+			let run = self.data[self.offset..].iter()
+				.take_while(|&&c| c != b'"' && c != b'\\' && c != b'\n')
+				.count();
+			out.extend_from_slice(&self.data[self.offset .. self.offset + run]);
+			self.offset += run;
+			self.position.column += run;
+
+			let position = self.position;
+			match self.bump() {
+				None | Some(b'\n') => return self.error(start, "unterminated string"),
+				Some(b'"') => return Ok(()),
+				Some(b'\\') => out.push(match self.bump() {
+					Some(b'\\') => b'\\',
+					Some(b'"') => b'"',
+					Some(b'\'') => b'\'',
+					Some(b'n') => b'\n',
+					Some(b'r') => b'\r',
+					Some(b't') => b'\t',
+					_ => return self.error(position, "unsupported escape sequence"),
+				}),
+				Some(c) => out.push(c),
+			}
+		}
+	}
+
+	/// parses a string made of one or more adjacent literals
+	fn string(&mut self) -> Result<String, SyntaxError> {
+		let start = self.position;
+		let mut out = vec![];
+
+		self.string_literal(&mut out)?;
+		loop {
+			self.whitespace()?;
+			if self.peek() != Some(b'"') { break }
+			self.string_literal(&mut out)?;
+		}
+
+		String::from_utf8(out).or_else(|_| self.error(start, "string is not valid UTF-8"))
+	}
+
+	fn entry(&mut self, warnings: &mut Vec<SyntaxError>) -> Result<Entry, SyntaxError> {
+		let start = self.position;
+		self.expect(b'{')?;
+
+		let mut fields = vec![];
+		let mut extra = None;
+		loop {
+			self.whitespace()?;
+			if self.peek() == Some(b'}') { break }
+
+			let position = self.position;
+			let s = self.string()?;
+			if fields.len() < 5 {
+				fields.push(s);
+			} else if extra.is_none() {
+				extra = Some(position);
+			}
+
+			self.whitespace()?;
+			match self.peek() {
+				Some(b',') => { self.bump(); },
+				Some(b'}') => break,
+				_ => {
+					let position = self.position;
+					return self.error(position, &format!("expected ',' or '}}', found {}", self.describe_next()));
+				},
+			}
+		}
+		self.expect(b'}')?;
+
+		if fields.len() < 5 {
+			return self.error(start, &format!("expected 5 fields, found {}", fields.len()));
+		}
+		if let Some(position) = extra {
+			warnings.push(SyntaxError {
+				position,
+				message: "unknown fields are ignored".to_string(),
+			});
+		}
+
+		let mut fields = fields.into_iter();
+		let mut field = || fields.next().unwrap_or_default();
+		Ok(Entry {
+			family: field(),
+			model: field(),
+			firmware: field(),
+			warning: field(),
+			presets: field(),
+			// file name is filled in by the loader
+			origin: Some(Origin { file: String::new(), position: start }),
+		})
+	}
+
+	/// skips everything up to the end of the entry that starts at current position, hopefully
+	fn skip_entry(&mut self) {
+		let mut in_string = false;
+		while let Some(c) = self.bump() {
+			match c {
+				b'\\' if in_string => { self.bump(); },
+				b'"' => in_string = ! in_string,
+				// strings cannot span multiple lines, so there's no point in looking for its end any further
+				b'\n' => in_string = false,
+				b'}' if ! in_string => return,
+				_ => (),
+			}
+		}
+	}
+}
+
+/**
+Parses the whole database.
+
+Returns entries along with non-fatal problems found in the file.
+If `lenient` is set, malformed entries are skipped and reported as such problems as well instead of failing the whole database.
+*/
+pub fn database(data: &[u8], lenient: bool) -> Result<(Vec<Entry>, Vec<SyntaxError>), SyntaxError> {
+	let mut p = Parser::new(data);
+	let mut entries = vec![];
+	let mut warnings = vec![];
+
+	loop {
+		p.whitespace()?;
+		if p.peek().is_none() { break }
+
+		let (offset, position) = (p.offset, p.position);
+		match p.entry(&mut warnings) {
+			Ok(entry) => entries.push(entry),
+			Err(err) => if lenient {
+				warnings.push(err);
+				// start over, this time ignoring entry contents
+				p.offset = offset;
+				p.position = position;
+				p.skip_entry();
+			} else {
+				return Err(err);
+			},
+		}
+
+		// trailing comma is optional, just like in C
+		p.whitespace()?;
+		if p.peek() == Some(b',') { p.bump(); }
+	}
+
+	if entries.is_empty() && ! lenient {
+		let position = p.position;
+		return p.error(position, "no entries found");
+	}
+
+	let entries = entries.into_iter()
+		.filter(Entry::is_enabled)
+		.collect();
+	Ok((entries, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(db: &str) -> Result<(Vec<Entry>, Vec<SyntaxError>), SyntaxError> {
+		database(db.as_bytes(), false)
+	}
+
+	fn error(db: &[u8]) -> (usize, usize, String) {
+		let err = database(db, false).unwrap_err();
+		(err.position.line, err.position.column, err.message)
+	}
+
+	fn fields(e: &Entry) -> [&str; 5] {
+		[&e.family, &e.model, &e.firmware, &e.warning, &e.presets]
+	}
+
+	#[test]
+	fn entries() {
+		let (entries, warnings) = parse(r#"
+			{ "Family", "Model", "Firmware", "Warning", "-v 9,minutes" },
+			{ "Family 2", "Model 2", "", "", "" }
+		"#).unwrap();
+		assert!(warnings.is_empty());
+		assert_eq!(entries.len(), 2);
+		assert_eq!(fields(&entries[0]), ["Family", "Model", "Firmware", "Warning", "-v 9,minutes"]);
+		assert_eq!(fields(&entries[1]), ["Family 2", "Model 2", "", "", ""]);
+		assert_eq!(entries[1].origin, Some(Origin { file: String::new(), position: Position { line: 3, column: 4 } }));
+	}
+
+	#[test]
+	fn concatenation() {
+		let (entries, _) = parse(concat!(
+			"{ \"Fam\" /* comment */ \"ily\", // comment\n",
+			"  \"Mo\"\n",
+			"  // comment\n",
+			"  \"del\", \"\", \"\", \"\" }\n",
+		)).unwrap();
+		assert_eq!(entries[0].family, "Family");
+		assert_eq!(entries[0].model, "Model");
+	}
+
+	#[test]
+	fn escapes() {
+		let (entries, _) = parse(r#"{ "\"\\\'", "\n\r\t", "", "", "" }"#).unwrap();
+		assert_eq!(entries[0].family, "\"\\'");
+		assert_eq!(entries[0].model, "\n\r\t");
+
+		assert_eq!(error(br#"{ "F", "a\x41", "", "", "" }"#), (1, 10, "unsupported escape sequence".to_string()));
+	}
+
+	#[test]
+	fn trailing_commas() {
+		// after the last field, and between entries
+		let (entries, _) = parse(r#"{ "A", "a", "", "", "", }{ "B", "b", "", "", "" },"#).unwrap();
+		assert_eq!(entries.len(), 2);
+	}
+
+	#[test]
+	fn unknown_fields() {
+		let (entries, warnings) = parse(r#"{ "F", "M", "", "", "", "extra", "more" }"#).unwrap();
+		assert_eq!(fields(&entries[0]), ["F", "M", "", "", ""]);
+		assert_eq!(warnings.len(), 1);
+		assert_eq!(warnings[0].position, Position { line: 1, column: 25 });
+		assert_eq!(warnings[0].message, "unknown fields are ignored");
+	}
+
+	#[test]
+	fn disabled_entries() {
+		let (entries, _) = parse(r#"{ "$Disabled", "a", "", "", "" }, { "Enabled", "b", "", "", "" }"#).unwrap();
+		assert_eq!(entries.len(), 1);
+		assert_eq!(entries[0].family, "Enabled");
+	}
+
+	#[test]
+	fn errors() {
+		assert_eq!(error(br#"{ "F", "M", "", "" }"#), (1, 1, "expected 5 fields, found 4".to_string()));
+		assert_eq!(error(b"\n{ \"F\", \"M\" \"\", \"\"; \"\" }"), (2, 18, "expected ',' or '}', found ';'".to_string()));
+		assert_eq!(error(b"{ \"F\", \"M\n\", \"\", \"\", \"\" }"), (1, 8, "unterminated string".to_string()));
+		assert_eq!(error(br#"{ "F", /* "M", "", "", "" }"#), (1, 8, "unterminated comment".to_string()));
+		assert_eq!(error(b"{ \"\xff\", \"M\", \"\", \"\", \"\" }"), (1, 3, "string is not valid UTF-8".to_string()));
+		assert_eq!(error(b"\n\n  foo"), (3, 3, "expected '{', found 'f'".to_string()));
+		assert_eq!(error(br#"{ "F", "M", "", "", """#), (1, 23, "expected ',' or '}', found end of file".to_string()));
+		assert_eq!(error(b"  // nothing here\n"), (2, 1, "no entries found".to_string()));
+
+		let err = parse("\n\n  foo").unwrap_err();
+		assert_eq!(err.to_string(), "3:3: expected '{', found 'f'");
+	}
+
+	#[test]
+	fn lenient() {
+		let db = r#"
+{ "A", "a", "", "", "" },
+{ "B", "b}", x },
+{ "C", "c" },
+{ "D", "d", "", "", "" }
+"#;
+		let err = parse(db).unwrap_err();
+		assert_eq!(err.position, Position { line: 3, column: 14 });
+
+		let (entries, warnings) = database(db.as_bytes(), true).unwrap();
+		let families: Vec<_> = entries.iter().map(|e| e.family.as_str()).collect();
+		assert_eq!(families, ["A", "D"]);
+		let warnings: Vec<_> = warnings.iter().map(|w| (w.position.line, w.position.column, w.message.as_str())).collect();
+		assert_eq!(warnings, [
+			(3, 14, "expected '\"', found 'x'"),
+			(4, 1, "expected 5 fields, found 2"),
+		]);
+
+		// there's nothing to complain about in an empty file unless entries are required
+		assert!(database(b"", true).unwrap().0.is_empty());
+	}
+}
@@ -1,6 +1,48 @@
 use super::vendor_attribute;
 use super::vendor_attribute::Attribute;
 
+use std::fmt;
+
+/**
+Device type that drive database recommends for particular drives or USB bridges (`-d` option of drivedb presets, e.g. `-d sat,12`).
+
+See `-d` option in [smartctl(8)](https://www.smartmontools.org/browser/trunk/smartmontools/smartctl.8.in) for the list of types and their options.
+*/
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RecommendedType {
+	/// pass-through protocol, like `sat` or `usbjmicron`; `unsupported` means device is known not to work at all
+	pub protocol: String,
+	/// comma-separated options that follow protocol name, like `12` in `sat,12`
+	pub options: Vec<String>,
+}
+
+impl RecommendedType {
+	fn parse(s: &str) -> Self {
+		let mut parts = s.split(',').map(|s| s.to_string());
+		RecommendedType {
+			protocol: parts.next().unwrap_or_default(),
+			options: parts.collect(),
+		}
+	}
+
+	/// Returns `false` if the database says there's no way to talk to the device (`-d unsupported`).
+	pub fn is_supported(&self) -> bool {
+		self.protocol != "unsupported"
+	}
+}
+
+impl fmt::Display for RecommendedType {
+	/// Formats the type just like it would be passed to `smartctl -d`.
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.protocol)?;
+		for opt in &self.options {
+			write!(f, ",{}", opt)?;
+		}
+		Ok(())
+	}
+}
+
 pub fn parse(line: &str) -> Option<Vec<Attribute>> {
 	// using clap here would be an overkill
 	let mut args = line.split_whitespace().into_iter();
@@ -23,3 +65,43 @@ pub fn parse(line: &str) -> Option<Vec<Attribute>> {
 		}
 	}
 }
+
+// `-F` arguments known to smartmontools (`parse_firmwarebug_def`, atacmds.cpp)
+const FIRMWARE_BUGS: &[&str] = &["none", "nologdir", "samsung", "samsung2", "samsung3", "xerrorlba", "swapid"];
+
+/// Lists problems with presets, like malformed attribute descriptions or unknown options, that `parse()` silently ignores
+pub fn validate(line: &str) -> Vec<String> {
+	let mut problems = vec![];
+	let mut args = line.split_whitespace();
+	while let Some(key) = args.next() {
+		let value = match args.next() {
+			Some(value) => value,
+			None => {
+				problems.push(format!("option {} lacks an argument", key));
+				break;
+			},
+		};
+		match key {
+			"-v" => if let Err(err) = vendor_attribute::parse(value) {
+				problems.push(err.to_string());
+			},
+			"-F" => if ! FIRMWARE_BUGS.contains(&value) {
+				problems.push(format!("unknown firmware bug {:?}", value));
+			},
+			"-d" => (),
+			_ => problems.push(format!("unknown option {}", key)),
+		}
+	}
+	problems
+}
+
+/// Looks for the argument of `-d` option
+pub fn device_type(line: &str) -> Option<RecommendedType> {
+	let mut args = line.split_whitespace();
+	while let Some(key) = args.next() {
+		if key == "-d" {
+			return args.next().map(RecommendedType::parse);
+		}
+	}
+	None
+}
@@ -0,0 +1,127 @@
+use super::loader::{self, Error};
+use super::parser::Entry;
+
+use std::io::prelude::*;
+
+use sha2::{Digest, Sha256};
+use ureq;
+
+/// Base URL of the smartmontools repository mirror that serves raw drivedb.h files.
+pub const UPSTREAM: &str = "https://raw.githubusercontent.com/smartmontools/smartmontools";
+
+fn sha256(data: &[u8]) -> String {
+	Sha256::digest(data).iter()
+		.map(|b| format!("{:02x}", b))
+		.collect()
+}
+
+pub fn fetch(url: &str, revision: &str, cache: &str, expected: &str) -> Result<(Vec<Entry>, Vec<Error>), Error> {
+	let url = format!("{}/{}/smartmontools/drivedb.h", url.trim_end_matches('/'), revision);
+
+	let mut db = Vec::new();
+	ureq::get(&url).call()
+		.map_err(|err| Error::Download(url.clone(), Box::new(err)))?
+		.into_reader()
+		.read_to_end(&mut db)
+		.map_err(|err| Error::IO(url.clone(), err))?;
+
+	let actual = sha256(&db);
+	if ! actual.eq_ignore_ascii_case(expected) {
+		return Err(Error::Checksum(url, expected.to_string(), actual));
+	}
+
+	// make sure this is an actual drive database (and not, say, some HTML error page) before replacing the cached copy;
+	// this is why malformed entries are never tolerated here
+	let (entries, warnings) = loader::parse(&url, &db, false)?;
+
+	loader::write(cache, &db)?;
+
+	Ok((entries, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use super::super::Loader;
+	use std::env;
+	use std::fs;
+	use std::io::Write;
+	use std::net::TcpListener;
+	use std::process;
+	use std::thread;
+
+	const DB: &str = r#"{ "Family", "Model", "", "", "" },"#;
+
+	// serves `body` for a single request, returning the base URL to fetch it from
+	fn serve(body: &'static str) -> String {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+		thread::spawn(move || {
+			let (mut stream, _) = listener.accept().unwrap();
+			// read the request headers, they're of no interest though
+			let mut request = vec![];
+			let mut buf = [0; 1024];
+			while ! request.ends_with(b"\r\n\r\n") {
+				match stream.read(&mut buf) {
+					Ok(0) | Err(_) => break,
+					Ok(n) => request.extend_from_slice(&buf[..n]),
+				}
+			}
+			write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+		});
+		url
+	}
+
+	fn tmp(name: &str) -> String {
+		env::temp_dir()
+			.join(format!("drivedb-test-{}-{}", process::id(), name))
+			.to_string_lossy()
+			.into_owned()
+	}
+
+	#[test]
+	fn pinned() {
+		let cache = tmp("fetch.h");
+		let mut loader = Loader::new();
+		loader.fetch_pinned(&serve(DB), "abc123", &cache, &sha256(DB.as_bytes()).to_uppercase()).unwrap();
+		assert_eq!(fs::read_to_string(&cache).unwrap(), DB);
+
+		let db = loader.db().unwrap();
+		let meta = db.render_meta("Model", "", None, &vec![]);
+		assert_eq!(meta.family(), Some("Family"));
+		assert!(meta.origin().unwrap().file.ends_with("/abc123/smartmontools/drivedb.h"));
+
+		fs::remove_file(&cache).unwrap();
+	}
+
+	#[test]
+	fn checksum_mismatch() {
+		let cache = tmp("fetch-mismatch.h");
+		fs::write(&cache, "previous copy").unwrap();
+
+		let mut loader = Loader::new();
+		match loader.fetch_pinned(&serve(DB), "abc123", &cache, &sha256(b"something else")) {
+			Err(Error::Checksum(_, _, actual)) => assert_eq!(actual, sha256(DB.as_bytes())),
+			other => panic!("expected checksum mismatch, got {:?}", other),
+		}
+		assert_eq!(fs::read_to_string(&cache).unwrap(), "previous copy");
+
+		fs::remove_file(&cache).unwrap();
+	}
+
+	#[test]
+	fn malformed() {
+		let cache = tmp("fetch-malformed.h");
+		fs::write(&cache, "previous copy").unwrap();
+
+		const PAGE: &str = "<html>Not Found</html>";
+		let mut loader = Loader::new();
+		match loader.fetch_pinned(&serve(PAGE), "abc123", &cache, &sha256(PAGE.as_bytes())) {
+			Err(Error::Parse(..)) => (),
+			other => panic!("expected parse error, got {:?}", other),
+		}
+		assert_eq!(fs::read_to_string(&cache).unwrap(), "previous copy");
+
+		fs::remove_file(&cache).unwrap();
+	}
+}
@@ -0,0 +1,42 @@
+use super::parser::Entry;
+use super::presets::{self, RecommendedType};
+
+/**
+USB bridge entry of the drive database.
+
+Such entries describe USB enclosures and USB-to-ATA bridges rather than drives themselves,
+and tell which pass-through should be used to talk to the drive behind them.
+*/
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct UsbEntry {
+	/// Name of the USB device (e.g. enclosure), if known
+	pub device: Option<String>,
+	/// Name of the USB bridge chip, if known
+	pub bridge: Option<String>,
+	/// Recommended device type, if known
+	pub device_type: Option<RecommendedType>,
+
+	// original entry, for the cache and serialized output
+	pub(crate) entry: Entry,
+}
+
+impl UsbEntry {
+	pub(crate) fn is_usb(entry: &Entry) -> bool {
+		entry.family.starts_with("USB:")
+	}
+
+	pub(crate) fn new(entry: Entry) -> Self {
+		// > "USB: [DEVICE] ; [BRIDGE]"
+		let mut names = entry.family["USB:".len()..].splitn(2, ';')
+			.map(|s| s.trim())
+			.map(|s| if s.is_empty() { None } else { Some(s.to_string()) });
+
+		UsbEntry {
+			device: names.next().and_then(|s| s),
+			bridge: names.next().and_then(|s| s),
+			device_type: presets::device_type(&entry.presets),
+			entry,
+		}
+	}
+}
@@ -17,41 +17,141 @@ quick_error! {
 	#[derive(Debug)]
 	#[allow(missing_docs)] // self-explanatory
 	pub enum Error {
-		Parse {
+		Parse(attr: String) {
 			// TODO? Parse(nom::verbose_errors::Err) if dependencies.nom.features = ["verbose-errors"]
-			display("Unable to parse vendor attribute")
+			display("Unable to parse vendor attribute {:?}", attr)
 		}
 	}
 }
 
 /// HDD or SSD
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[allow(missing_docs)] // self-explanatory enum variants
 pub enum Type { HDD, SSD }
 
+impl Type {
+	/**
+	Guesses drive type from its nominal media rotation rate, as reported in word 217 of ATA IDENTIFY DEVICE data, or in Block Device Characteristics VPD page of SCSI devices (both use the same encoding).
+
+	Returns `None` if the rate is not reported or is reserved.
+
+	```
+	use drivedb::Type;
+
+	assert_eq!(Type::from_rotation_rate(7200), Some(Type::HDD));
+	assert_eq!(Type::from_rotation_rate(1), Some(Type::SSD));
+	assert_eq!(Type::from_rotation_rate(0), None);
+	```
+	*/
+	pub fn from_rotation_rate(rate: u16) -> Option<Type> {
+		match rate {
+			1 => Some(Type::SSD),
+			0x0401 ..= 0xfffe => Some(Type::HDD),
+			_ => None,
+		}
+	}
+}
+
 /// SMART attribute description
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Attribute {
 	/// id of described attribute
 	pub id: Option<u8>,
 	/// attribute name
 	pub name: Option<String>,
-	/// value format, like `raw48` or `tempminmax`
+	/// value format, like `raw48` or `tempminmax` (see [`FORMATS`](constant.FORMATS.html))
 	pub format: String,
 	/// bytes of attribute data to make value of (usually something like `r543210`, where `r`, `v`, `w` represent reserved byte, current and worst values respectively)
 	pub byte_order: String,
+	/// whether raw value is expected to never decrease, like counts of reallocated or pending sectors (`+` after the format)
+	pub increasing: bool,
 	/// what kind of device this description is applicable to: HDD, SSD, or both
 	pub drivetype: Option<Type>,
 }
 
+/// Which values of an attribute are worse than others
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(missing_docs)] // self-explanatory enum variants
+pub enum Direction { LowerIsWorse, HigherIsWorse }
+
+impl Attribute {
+	/**
+	Tells how to interpret normalized (current and worst) values of the attribute.
+
+	Normalized values are always compared against the threshold, and the attribute is considered failing once its value drops to or below the threshold,
+	so the only question is whether this attribute has normalized value at all: it does not if it is used as a part of the raw value (byte order contains `v`).
+	*/
+	pub fn normalized_direction(&self) -> Option<Direction> {
+		if self.byte_order.contains('v') {
+			None
+		} else {
+			Some(Direction::LowerIsWorse)
+		}
+	}
+
+	/**
+	Tells how to interpret raw value of the attribute, if that is known.
+
+	This is inferred from the attribute format (temperatures, or values marked with `+`) and its id (well-known error counters like reallocated or pending sectors);
+	most of the raw values are vendor-specific and are not meant to be compared at all, hence `None`.
+	*/
+	pub fn raw_direction(&self) -> Option<Direction> {
+		if self.increasing {
+			return Some(Direction::HigherIsWorse);
+		}
+
+		match self.format.as_str() {
+			"tempminmax" | "temp10x" => return Some(Direction::HigherIsWorse),
+			_ => (),
+		}
+
+		match self.id {
+			// Reallocated_Sector_Ct, Spin_Retry_Count, Calibration_Retry_Count, End-to-End_Error, Reported_Uncorrect, Command_Timeout,
+			// Reallocated_Event_Count, Current_Pending_Sector, Offline_Uncorrectable, UDMA_CRC_Error_Count
+			Some(5) | Some(10) | Some(11) | Some(184) | Some(187) | Some(188)
+			| Some(196) | Some(197) | Some(198) | Some(199) => Some(Direction::HigherIsWorse),
+			_ => None,
+		}
+	}
+}
+
+/// Value formats known to smartmontools (`format_names`, atacmds.cpp)
+pub const FORMATS: &[&str] = &[
+	"raw8",
+	"raw16",
+	"raw48",
+	"hex48",
+	"raw56",
+	"hex56",
+	"raw64",
+	"hex64",
+	"raw16(raw16)",
+	"raw16(avg16)",
+	"raw24(raw8)",
+	"raw24/raw24",
+	"raw24/raw32",
+	"sec2hour",
+	"min2hour",
+	"halfmin2hour",
+	"msec24hour32",
+	"tempminmax",
+	"temp10x",
+];
+
 fn not_comma(c: u8) -> bool { c == b',' }
 fn not_comma_nor_colon(c: u8) -> bool { c == b',' || c == b':' }
 
 // `opt!()` is used with `complete!()` here because the former returns `Incomplete` untouched, thus making attributes not ending with otherwise optional ',(HDD|SSD)' `Incomplete` as well.
-named!(parse_standard <Attribute>, do_parse!(
+// Note that this parser is quite permissive: it does not validate values, this is done later in `parse()`.
+named!(parse_standard <(Option<u8>, String, Option<String>, Option<String>, Option<Type>)>, do_parse!(
 	id: alt!(
-		// XXX map_res!()?
-		map!(digit, |x: &[u8]| str::from_utf8(x).unwrap().parse::<u8>().ok())
+		map!(
+			map_res!(map_res!(digit, str::from_utf8), str::parse::<u8>),
+			Some
+		)
 		// > If 'N' is specified as ID, the settings for all Attributes are changed
 		| do_parse!(char!('N') >> (None))
 	) >>
@@ -60,8 +160,7 @@ named!(parse_standard <Attribute>, do_parse!(
 		take_till1_s!(not_comma_nor_colon),
 		str::from_utf8
 	) >>
-	// TODO '+' for ATTRFLAG_INCREASING
-	byte_order: opt!(complete!(do_parse!( // TODO len()<6 should be invalid
+	byte_order: opt!(complete!(do_parse!(
 		char!(':') >>
 		byteorder: map_res!(
 			take_till1_s!(not_comma),
@@ -89,32 +188,98 @@ named!(parse_standard <Attribute>, do_parse!(
 	eof!() >>
 	({
 		let (name, drive_type) = match name_drive_type {
-			Some((name, drive_type)) => (Some(name), drive_type),
+			Some((name, drive_type)) => (Some(name.to_string()), drive_type),
 			None => (None, None),
 		};
-		let default_byte_order = match format {
-			// default byte orders, from ata_get_attr_raw_value, atacmds.cpp
-			"raw64" | "hex64" => "543210wv",
-			"raw56" | "hex56" | "raw24/raw32" | "msec24hour32" => "r543210",
-			_ => "543210",
-		};
-		Attribute {
-			id: id,
-			name: name.map(|x| x.to_string()),
-			format: format.to_string(),
-			byte_order: byte_order.unwrap_or(default_byte_order).to_string(),
-			drivetype: drive_type,
-		}
+		(id, format.to_string(), byte_order.map(|x| x.to_string()), name, drive_type)
 	})
 ));
 
+// mirrors checks from parse_attribute_def, atacmds.cpp
+fn validate(id: Option<u8>, format: &str, byte_order: Option<&str>, name: Option<&str>, drivetype: Option<Type>) -> Option<Attribute> {
+	if id == Some(0) { return None }
+
+	// > "N,format[,name]"
+	if id.is_none() && drivetype.is_some() { return None }
+
+	// '+' always comes last, i.e. after byte order, if there's one
+	let (format, byte_order, increasing) = match byte_order {
+		Some(b) if b.ends_with('+') => (format, Some(&b[..b.len() - 1]), true),
+		Some(_) => (format, byte_order, false),
+		None if format.ends_with('+') => (&format[..format.len() - 1], None, true),
+		None => (format, None, false),
+	};
+
+	if ! FORMATS.contains(&format) { return None }
+
+	if let Some(b) = byte_order {
+		if b.is_empty() || b.len() > 8 { return None }
+		if ! b.bytes().all(|c| b"012345rvwz".contains(&c)) { return None }
+	}
+
+	if let Some(name) = name {
+		if name.len() > 32 { return None }
+	}
+
+	let default_byte_order = match format {
+		// default byte orders, from ata_get_attr_raw_value, atacmds.cpp
+		"raw64" | "hex64" => "543210wv",
+		"raw56" | "hex56" | "raw24/raw32" | "msec24hour32" => "r543210",
+		_ => "543210",
+	};
+
+	Some(Attribute {
+		id,
+		name: name.map(|x| x.to_string()),
+		format: format.to_string(),
+		byte_order: byte_order.unwrap_or(default_byte_order).to_string(),
+		increasing,
+		drivetype,
+	})
+}
+
 /**
 Parses single attribute description (`-v` option argument).
 
 The following formats are supported:
 
-* `ID,FORMAT[:BYTEORDER][,NAME[,(HDD|SSD)]]`
-* legacy `-v` arguments, like `9,halfminutes`
+* `ID,FORMAT[:BYTEORDER][+][,NAME[,(HDD|SSD)]]`, where `ID` is within 1..255,
+* `N,FORMAT[:BYTEORDER][+][,NAME]`, which applies to all attributes at once,
+* legacy `-v` arguments, like `9,halfminutes`.
+
+`FORMAT` is one of the [`FORMATS`](constant.FORMATS.html), and `BYTEORDER` is made of up to 8 characters out of `012345rvwz`.
+Values are validated the same way smartmontools does it.
+
+## Example
+
+```
+use drivedb::vendor_attribute::{parse, Type};
+
+let attr = parse("197,raw48+,Total_Pending_Sectors,HDD").unwrap();
+assert_eq!(attr.id, Some(197));
+assert_eq!(attr.format, "raw48");
+assert_eq!(attr.byte_order, "543210");
+assert!(attr.increasing);
+assert_eq!(attr.drivetype, Some(Type::HDD));
+
+let attr = parse("N,raw8:012345").unwrap();
+assert_eq!(attr.id, None);
+assert_eq!(attr.byte_order, "012345");
+
+let attr = parse("9,raw24/raw32:r543210").unwrap();
+assert_eq!(attr.format, "raw24/raw32");
+assert_eq!(attr.byte_order, "r543210");
+
+let attr = parse("9,halfminutes").unwrap();
+assert_eq!(attr.format, "halfmin2hour");
+assert_eq!(attr.name, Some("Power_On_Half_Minutes".to_string()));
+
+assert!(parse("0,raw48").is_err()); // invalid id
+assert!(parse("256,raw48").is_err()); // ditto
+assert!(parse("9,raw49").is_err()); // unknown format
+assert!(parse("9,raw48:543219").is_err()); // invalid byte order
+assert!(parse("N,raw48,Name,HDD").is_err()); // drive type cannot be specified for all attributes at once
+```
 */
 pub fn parse(s: &str) -> Result<Attribute, Error> {
 	let s = match s {
@@ -136,8 +301,10 @@ pub fn parse(s: &str) -> Result<Attribute, Error> {
 	};
 	// FIXME strings to bytes to strings again… sounds really stupid
 	match parse_standard(s.as_bytes()) {
-		nom::IResult::Done(_, attr) => Ok(attr),
-		nom::IResult::Error(_) => Err(Error::Parse), // TODO?
-		nom::IResult::Incomplete(_) => Err(Error::Parse), // TODO?
+		nom::IResult::Done(_, (id, format, byte_order, name, drivetype)) =>
+			validate(id, &format, byte_order.as_deref(), name.as_deref(), drivetype)
+			.ok_or_else(|| Error::Parse(s.to_string())),
+		nom::IResult::Error(_) => Err(Error::Parse(s.to_string())), // TODO?
+		nom::IResult::Incomplete(_) => Err(Error::Parse(s.to_string())), // TODO?
 	}
 }
@@ -0,0 +1,222 @@
+use super::parser::Entry;
+use regex::bytes::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+
+/// How serious the issue the database warns about is
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Severity {
+	/// Something worth knowing about, like misreported attribute values
+	Info,
+	/// Firmware update is available
+	FirmwareUpdate,
+	/// Drive might hang, lose data or fail; usually there's a firmware update to fix that as well
+	Critical,
+}
+
+/**
+Warning that accompanies drive database entry.
+
+All the fields besides `text` are inferred from the warning text and entry itself on a best-effort basis, as drivedb warnings are nothing but free-form text meant for humans.
+*/
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Warning<'a> {
+	/// how serious the issue is, judging by the wording of the warning
+	pub severity: Severity,
+	/// > A message that may be displayed for matching drives.
+	/// > For example, to inform the user that they may need to apply a firmware patch.
+	pub text: &'a str,
+	/// regex of firmware versions this warning applies to, unless the entry applies to any firmware
+	pub affected_firmware: Option<&'a str>,
+	/// firmware version that is said to fix the issue, if the warning mentions one
+	pub fixed_firmware: Option<String>,
+
+	// compiled `affected_firmware`
+	#[cfg_attr(feature = "serde", serde(skip))]
+	affected_regex: Option<&'a Regex>,
+}
+
+/**
+Parts of the warning that are too expensive to infer every time the entry is matched.
+
+These are computed for every entry that carries a warning once the database is loaded.
+*/
+#[derive(Debug)]
+pub(crate) struct Inferred {
+	severity: Severity,
+	fixed_firmware: Option<String>,
+	affected_regex: Option<Regex>,
+}
+
+// regexes used to make sense of the warning text
+struct Heuristics {
+	critical: Regex,
+	update: Regex,
+	fixed: Regex,
+}
+
+impl Heuristics {
+	fn new() -> Self {
+		Heuristics {
+			// whole words only: "hang" is not "change"
+			critical: Regex::new(r"(?i)\b(?:hangs?|hung|data\s+loss|lose\s+data|corrupt\w*|brick\w*|may\s+fail)\b").unwrap(),
+			update: Regex::new(r"(?i)\b(?:firmware\s+(?:update|upgrade)|(?:update|upgrade)\s+(?:the\s+)?firmware|(?:latest|new)\s+firmware|(?:update|upgrade)\s+to)\b").unwrap(),
+			// e.g. "fixed in firmware version XYZ1", "please update to firmware XYZ1"
+			fixed: Regex::new(r"(?i)(?:fixed|resolved|update|upgrade)\s+(?:in|by|with|to)\s+(?:the\s+)?(?:firmware\s+)?(?:version\s+|revision\s+)?([0-9A-Z][0-9A-Z.]*[0-9A-Z])").unwrap(),
+		}
+	}
+
+	fn severity(&self, text: &str, fixed_firmware: Option<&str>) -> Severity {
+		if self.critical.is_match(text.as_bytes()) {
+			Severity::Critical
+		// if there's a version that fixes the issue, there's an update for sure
+		} else if fixed_firmware.is_some() || self.update.is_match(text.as_bytes()) {
+			Severity::FirmwareUpdate
+		} else {
+			Severity::Info
+		}
+	}
+
+	fn fixed_firmware(&self, text: &str) -> Option<String> {
+		self.fixed.captures_iter(text.as_bytes())
+			.filter_map(|c| c.get(1))
+			.map(|m| m.as_bytes())
+			// skip things like "update to the latest firmware"
+			.find(|version| version.iter().any(|c| c.is_ascii_digit()))
+			.map(|version| String::from_utf8_lossy(version).into_owned())
+	}
+}
+
+impl Inferred {
+	/// Returns inferred warning details for each of the `entries`, or `None` for entries without warnings.
+	pub(crate) fn all(entries: &[Entry]) -> Vec<Option<Self>> {
+		let heuristics = Heuristics::new();
+
+		entries.iter().map(|entry| {
+			if entry.warning.is_empty() { return None }
+			let fixed_firmware = heuristics.fixed_firmware(&entry.warning);
+			Some(Inferred {
+				severity: heuristics.severity(&entry.warning, fixed_firmware.as_deref()),
+				fixed_firmware,
+				// model firmware regex is a part of the database that was already compiled once, so it's known to be valid
+				affected_regex: if entry.firmware.is_empty() { None } else {
+					RegexBuilder::new(&format!("^(?:{})$", entry.firmware)).unicode(false).build().ok()
+				},
+			})
+		}).collect()
+	}
+}
+
+/*
+Firmware versions follow no common scheme, so only two kinds of them are compared:
+- dotted numeric versions (`2.10`), component by component,
+- versions of the same length that share the same non-numeric prefix (`CC24`, `CC4H`), character by character.
+*/
+fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+	let numeric = |v: &str| v.split('.')
+		.map(|part| part.parse::<u64>().ok())
+		.collect::<Option<Vec<_>>>();
+	if let (Some(a), Some(b)) = (numeric(a), numeric(b)) {
+		return Some(a.cmp(&b));
+	}
+
+	let prefix = |v: &str| v.bytes().take_while(|c| ! c.is_ascii_digit()).count();
+	let (a, b) = (a.to_uppercase(), b.to_uppercase());
+	if a.len() == b.len() && prefix(&a) > 0 && a[..prefix(&a)] == b[..prefix(&b)] {
+		Some(a.cmp(&b))
+	} else {
+		None
+	}
+}
+
+impl<'a> Warning<'a> {
+	pub(crate) fn new(entry: &'a Entry, inferred: &'a Inferred) -> Self {
+		Warning {
+			severity: inferred.severity,
+			text: &entry.warning,
+			affected_firmware: if entry.firmware.is_empty() { None } else { Some(&entry.firmware) },
+			fixed_firmware: inferred.fixed_firmware.clone(),
+			affected_regex: inferred.affected_regex.as_ref(),
+		}
+	}
+
+	/**
+	Tells whether the issue is resolved in given `firmware` version, if possible.
+
+	Returns:
+
+	- `Some(false)` if `firmware` is among the affected ones, or if it is older than the fixed version,
+	- `Some(true)` if it is the same as or newer than the fixed version, or if it is not among the affected ones and the warning mentions no fixed version,
+	- `None` if there's no way to tell, e.g. if versions cannot be compared.
+
+	Firmware versions are only compared if both are dotted numbers (so `2.10` is newer than `2.9`), or if they are of the same length and share the same non-numeric prefix, in which case they are compared character by character (so `CC4H` is newer than `CC24`).
+
+	```
+	let mut loader = drivedb::Loader::new();
+	loader.load_from_str(r#"{ "Family", "Model", "CC2[0-5]", "Drive may hang, fixed in firmware CC4H", "" }"#).unwrap();
+	let db = loader.db().unwrap();
+
+	let meta = db.render_meta("Model", "CC24", None, &vec![]);
+	let warning = meta.warning.unwrap();
+	assert_eq!(warning.fixed_firmware.as_deref(), Some("CC4H"));
+	assert_eq!(warning.is_fixed_in("CC24"), Some(false));
+	assert_eq!(warning.is_fixed_in("CC4H"), Some(true));
+	assert_eq!(warning.is_fixed_in("CC26"), Some(false)); // not affected, but older than the fix
+	assert_eq!(warning.is_fixed_in("CC4H1"), None);
+	assert_eq!(warning.is_fixed_in("DD30"), None);
+	```
+	*/
+	pub fn is_fixed_in(&self, firmware: &str) -> Option<bool> {
+		if let Some(re) = self.affected_regex {
+			if re.is_match(firmware.as_bytes()) { return Some(false) }
+			if self.fixed_firmware.is_none() { return Some(true) }
+		}
+
+		self.fixed_firmware.as_ref()
+			.and_then(|fixed| compare_versions(firmware, fixed))
+			.map(|ord| ord != Ordering::Less)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn severity() {
+		let h = Heuristics::new();
+		let severity = |text| h.severity(text, h.fixed_firmware(text).as_deref());
+
+		assert_eq!(severity("Drive may hang after 32,768 hours of operation"), Severity::Critical);
+		assert_eq!(severity("Firmware bug: drive hangs on power loss"), Severity::Critical);
+		assert_eq!(severity("Data loss possible, update to firmware XY12"), Severity::Critical);
+		assert_eq!(severity("Drive may become bricked"), Severity::Critical);
+		assert_eq!(severity("Firmware change recommended: see vendor page"), Severity::Info);
+		assert_eq!(severity("Exchange the cable if CRC errors keep growing"), Severity::Info);
+		assert_eq!(severity("Hangout"), Severity::Info);
+		assert_eq!(severity("A firmware update for this drive may be available"), Severity::FirmwareUpdate);
+		assert_eq!(severity("Please update to firmware XY12"), Severity::FirmwareUpdate);
+		assert_eq!(severity("This is fixed in version 2.10"), Severity::FirmwareUpdate);
+		assert_eq!(severity("Attribute 9 is misreported"), Severity::Info);
+	}
+
+	#[test]
+	fn fixed_firmware() {
+		let h = Heuristics::new();
+		assert_eq!(h.fixed_firmware("Please update to firmware XY12").as_deref(), Some("XY12"));
+		assert_eq!(h.fixed_firmware("Fixed in firmware version CC4H.").as_deref(), Some("CC4H"));
+		assert_eq!(h.fixed_firmware("Update to the latest firmware"), None);
+	}
+
+	#[test]
+	fn versions() {
+		assert_eq!(compare_versions("10.0", "2.10"), Some(Ordering::Greater));
+		assert_eq!(compare_versions("2.9", "2.10"), Some(Ordering::Less));
+		assert_eq!(compare_versions("2.10", "2.10"), Some(Ordering::Equal));
+		assert_eq!(compare_versions("CC4H", "cc24"), Some(Ordering::Greater));
+		assert_eq!(compare_versions("CC24", "DD24"), None);
+		assert_eq!(compare_versions("1A2B", "1A2C"), None);
+		assert_eq!(compare_versions("CC24", "CC241"), None);
+	}
+}